@@ -0,0 +1,183 @@
+use indextree::NodeId;
+use orgize::elements::Element;
+use orgize::{Headline, Org};
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::RwLock;
+
+use crate::diagnostics::{Kind, Location};
+use crate::handlers::is_image;
+use crate::site::{Article, Heading, Id, Page, Resolved, ResolvedFile, Site, UnresolvedLinks};
+
+/// An article, page or note whose links are checked.
+struct Source<'a> {
+    org: &'a RwLock<Org<'static>>,
+    headline: &'a Headline,
+    headings: &'a [Heading],
+    location: &'a Location,
+    link_locations: &'a HashMap<NodeId, Location>,
+    /// drafts may link to other drafts
+    is_draft: bool,
+}
+
+impl<'a> From<&'a Article> for Source<'a> {
+    fn from(article: &'a Article) -> Self {
+        Source {
+            org: &article.org,
+            headline: &article.headline,
+            headings: &article.headings,
+            location: &article.location,
+            link_locations: &article.link_locations,
+            is_draft: article.is_draft,
+        }
+    }
+}
+
+impl<'a> From<&'a Page> for Source<'a> {
+    fn from(page: &'a Page) -> Self {
+        Source {
+            org: &page.org,
+            headline: &page.headline,
+            headings: &page.headings,
+            location: &page.location,
+            link_locations: &page.link_locations,
+            is_draft: false,
+        }
+    }
+}
+
+/// Validate loaded articles (and pages and notes) and record problems into `site.diagnostics`.
+///
+/// `site` should be loaded with drafts and future articles to check them too.
+pub fn check(site: &Site) {
+    for article in site.articles.values().chain(site.drafts.values()) {
+        if article.is_future {
+            site.diagnostics.warn(
+                Kind::FutureArticle,
+                Some(article.location.clone()),
+                format!(
+                    "\"{}\" is scheduled in the future ({})",
+                    article.title, article.published
+                ),
+            );
+        }
+        check_links(site, article.as_ref().into());
+    }
+    for page in site.pages.values().chain(site.notes.values()) {
+        check_links(site, page.as_ref().into());
+    }
+}
+
+fn check_links(site: &Site, source: Source) {
+    let org = source.org.read().unwrap();
+    for node in source.headline.headline_node().descendants(org.arena()) {
+        let Element::Link(link) = &org[node] else {
+            continue;
+        };
+        let location = source.link_locations.get(&node).unwrap_or(source.location);
+        if let Some(id) = link.path.strip_prefix("id:") {
+            let id = Id::new(id.to_string());
+            match site.resolve_id(&id) {
+                Resolved::Article(target, _) => {
+                    if target.is_draft && !source.is_draft {
+                        site.diagnostics.warn(
+                            Kind::LinkToDraft,
+                            Some(location.clone()),
+                            format!("id:{} is a draft", id),
+                        );
+                    }
+                }
+                Resolved::ExcludedDraft => {
+                    site.diagnostics.warn(
+                        Kind::LinkToDraft,
+                        Some(location.clone()),
+                        format!("id:{} is a draft", id),
+                    );
+                }
+                // only when checked without future articles
+                Resolved::Page(..) | Resolved::Note(_) | Resolved::Embargoed => {}
+                Resolved::Unpublished => {
+                    if let UnresolvedLinks::Text | UnresolvedLinks::Note = site.unresolved_links {
+                        site.diagnostics.warn(
+                            Kind::BrokenLink,
+                            Some(location.clone()),
+                            format!("id:{} is not published", id),
+                        );
+                    }
+                }
+                Resolved::NotFound => {
+                    site.diagnostics.warn(
+                        Kind::BrokenLink,
+                        Some(location.clone()),
+                        format!("id:{} not found", id),
+                    );
+                }
+            }
+        } else if link.path.starts_with('*') || link.path.starts_with('#') {
+            if source.headings.iter().any(|h| h.matches(&link.path)) {
+                continue;
+            }
+            match site.resolve_heading(&link.path) {
+                Some((target, _)) => {
+                    if target.is_draft && !source.is_draft {
+                        site.diagnostics.warn(
+                            Kind::LinkToDraft,
+                            Some(location.clone()),
+                            format!("{} is in a draft", link.path),
+                        );
+                    }
+                }
+                None => {
+                    site.diagnostics.warn(
+                        Kind::BrokenLink,
+                        Some(location.clone()),
+                        format!("{} not found", link.path),
+                    );
+                }
+            }
+        } else if let Some(path) = link.path.strip_prefix("file:") {
+            let from = location.file.as_deref();
+            let message = match site.resolve_file(from, path) {
+                ResolvedFile::Article(target, _) => {
+                    if target.is_draft && !source.is_draft {
+                        site.diagnostics.warn(
+                            Kind::LinkToDraft,
+                            Some(location.clone()),
+                            format!("{} is a draft", link.path),
+                        );
+                    }
+                    continue;
+                }
+                ResolvedFile::Ambiguous(n) => Some(format!(
+                    "{} has {} articles, specify one with \"::*Heading\"",
+                    link.path, n
+                )),
+                ResolvedFile::Unpublished => Some(format!("{} is not published", link.path)),
+                ResolvedFile::NotFound => Some(format!("{} not found", link.path)),
+                ResolvedFile::NotInput => None,
+            };
+            if let Some(message) = message {
+                site.diagnostics
+                    .warn(Kind::BrokenLink, Some(location.clone()), message);
+                continue;
+            }
+            // strip search option (e.g. "file:foo.org::*heading")
+            let path = path.split_once("::").map(|(p, _)| p).unwrap_or(path);
+            let dir = from.and_then(Path::parent).unwrap_or(Path::new(""));
+            if !dir.join(path).exists() {
+                site.diagnostics.warn(
+                    Kind::MissingFile,
+                    Some(location.clone()),
+                    format!("file:{} does not exist", path),
+                );
+            }
+        }
+        if is_image(&link.path) && link.desc.is_none() {
+            site.diagnostics.warn(
+                Kind::MissingAltText,
+                Some(location.clone()),
+                format!("image {} has no description (alt text)", link.path),
+            );
+        }
+    }
+}
@@ -0,0 +1,175 @@
+use serde::Serialize;
+use std::cmp::Ordering;
+use std::fmt;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Mutex;
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Kind {
+    /// headline has blog tag, but no publish date
+    MissingPublishDate,
+    /// headline has blog tag, but no ID (or ID is empty)
+    MissingId,
+    /// property value couldn't be parsed
+    InvalidProperty,
+    /// `id:` link target is not found
+    BrokenLink,
+    /// same ID is used by more than one headline
+    DuplicateId,
+    /// published article links to a draft
+    LinkToDraft,
+    /// `file:` link target doesn't exist
+    MissingFile,
+    /// image link doesn't have a description
+    MissingAltText,
+    /// article is scheduled in the future
+    FutureArticle,
+    /// local time is ambiguous or doesn't exist in the site's time zone
+    InvalidLocalTime,
+    /// `#+INCLUDE:` or `#+SETUPFILE:` couldn't be understood
+    InvalidInclude,
+    /// file includes itself (directly or indirectly)
+    IncludeCycle,
+}
+
+/// Where a diagnostic comes from (`line` is 1-origin).
+#[derive(Debug, Clone, Eq, PartialEq, Ord, PartialOrd, Serialize)]
+pub struct Location {
+    pub file: Option<PathBuf>,
+    pub line: usize,
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(file) = &self.file {
+            write!(f, "{}:{}", file.display(), self.line)
+        } else {
+            write!(f, "<input>:{}", self.line)
+        }
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Serialize)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub kind: Kind,
+    #[serde(flatten)]
+    pub location: Option<Location>,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Format {
+    Text,
+    Json,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(Format::Text),
+            "json" => Ok(Format::Json),
+            _ => Err(format!("unknown diagnostics format: {}", s)),
+        }
+    }
+}
+
+/// Problems found while loading and generating a site.
+#[derive(Default)]
+pub struct Diagnostics {
+    diagnostics: Mutex<Vec<Diagnostic>>,
+}
+
+impl Diagnostics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn warn(&self, kind: Kind, location: Option<Location>, message: String) {
+        self.push(Diagnostic {
+            severity: Severity::Warning,
+            kind,
+            location,
+            message,
+        });
+    }
+    pub fn push(&self, diagnostic: Diagnostic) {
+        let mut diagnostics = self.diagnostics.lock().unwrap();
+        // same article may be rendered more than once (e.g. for atom feed)
+        if !diagnostics.contains(&diagnostic) {
+            diagnostics.push(diagnostic);
+        }
+    }
+    pub fn count(&self, severity: Severity) -> usize {
+        self.diagnostics
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|d| d.severity == severity)
+            .count()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.diagnostics.lock().unwrap().is_empty()
+    }
+    /// All diagnostics, ordered by location.
+    ///
+    /// Articles are rendered in parallel, so the order they are pushed is not deterministic.
+    /// Diagnostics of the same location keep the order they are found.
+    pub fn all(&self) -> Vec<Diagnostic> {
+        let mut diagnostics = self.diagnostics.lock().unwrap().clone();
+        diagnostics.sort_by(|a, b| {
+            a.location.cmp(&b.location).then_with(|| {
+                if a.location.is_none() {
+                    a.message.cmp(&b.message)
+                } else {
+                    Ordering::Equal
+                }
+            })
+        });
+        diagnostics
+    }
+    /// All diagnostics as a JSON array.
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(&self.all()).unwrap()
+    }
+    /// Print all diagnostics and a summary (or a JSON array only) to stderr.
+    pub fn report(&self, format: Format) {
+        let diagnostics = self.all();
+        match format {
+            Format::Text => {
+                for d in diagnostics.iter() {
+                    let (label, color) = match d.severity {
+                        Severity::Warning => ("WARNING", 33),
+                        Severity::Error => ("ERROR", 31),
+                    };
+                    if let Some(location) = &d.location {
+                        eprintln!(
+                            "\x1b[{}m{}: {}: {}\x1b[0m",
+                            color, label, location, d.message
+                        );
+                    } else {
+                        eprintln!("\x1b[{}m{}: {}\x1b[0m", color, label, d.message);
+                    }
+                }
+                let errors = self.count(Severity::Error);
+                let warnings = self.count(Severity::Warning);
+                if errors > 0 || warnings > 0 {
+                    eprintln!("{} errors, {} warnings", errors, warnings);
+                }
+            }
+            Format::Json => {
+                eprintln!("{}", self.to_json());
+            }
+        }
+    }
+}
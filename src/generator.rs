@@ -1,14 +1,20 @@
+use crate::diagnostics::Kind;
 use crate::{handlers, site};
 use askama::Template;
-use atom_syndication::{ContentBuilder, EntryBuilder, FeedBuilder, LinkBuilder};
-use chrono::{DateTime, Local, NaiveDateTime, TimeZone, Utc};
+use atom_syndication::{ContentBuilder, EntryBuilder, FeedBuilder, LinkBuilder, PersonBuilder};
+use chrono::{DateTime, Datelike, Month, NaiveDateTime};
+use chrono_tz::Tz;
 use filetime::{set_file_mtime, FileTime};
 use orgize::export::{DefaultHtmlHandler, SyntectHtmlHandler};
+use rayon::prelude::*;
 use rust_embed::RustEmbed;
+use std::collections::{BTreeMap, BTreeSet};
 use std::fs;
-use std::io::{stdout, Result, Write};
+use std::io::{stdout, Error, Result, Write};
 use std::path::PathBuf;
+#[cfg(test)]
 use std::rc::Rc;
+use std::sync::Arc;
 
 #[derive(RustEmbed)]
 #[folder = "static/"]
@@ -16,19 +22,52 @@ use std::rc::Rc;
 #[prefix = "static/"]
 pub struct StaticFiles;
 
+type Index = BTreeMap<site::Year, BTreeSet<Arc<site::Article>>>;
+/// articles of a year by month
+type Months<'a> = BTreeMap<u32, Vec<&'a Arc<site::Article>>>;
+
 #[derive(Template)]
 #[template(path = "index.html")]
 struct IndexTemplate<'a> {
     site: &'a site::Site,
     base: String,
+    section: Option<&'a site::Section>,
+    /// URL prefix of the section (relative to `base`)
+    prefix: String,
+    index: &'a Index,
 }
 
+/// Articles published in a year (or a month of it).
 #[derive(Template)]
 #[template(path = "archive.html")]
 struct ArchiveTemplate<'a> {
     site: &'a site::Site,
     base: String,
+    section: Option<&'a site::Section>,
+    prefix: String,
     year: site::Year,
+    month: Option<u32>,
+    articles: Vec<&'a Arc<site::Article>>,
+    /// all months of the year, if `Site::calendar`
+    calendar: Vec<CalendarMonth>,
+}
+
+struct CalendarMonth {
+    month: u32,
+    name: &'static str,
+    /// number of articles published in the month
+    count: usize,
+}
+
+/// All articles grouped by year and month.
+#[derive(Template)]
+#[template(path = "archives.html")]
+struct ArchivesTemplate<'a> {
+    site: &'a site::Site,
+    base: String,
+    section: Option<&'a site::Section>,
+    prefix: String,
+    archives: Vec<(site::Year, Months<'a>)>,
 }
 
 #[derive(Template)]
@@ -38,6 +77,57 @@ struct ArticleTemplate<'a, 'b> {
     article: &'b site::Article,
     base: String,
     content: String,
+    series: Option<SeriesBox<'a>>,
+    related: Vec<&'a Arc<site::Article>>,
+}
+
+/// The series an article belongs to, shown on the article page.
+struct SeriesBox<'a> {
+    name: &'a str,
+    path: String,
+    parts: Vec<&'a Arc<site::Article>>,
+    /// position of the article in `parts` (1-origin)
+    position: usize,
+}
+
+impl<'a> SeriesBox<'a> {
+    fn new(site: &'a site::Site, article: &site::Article) -> Option<Self> {
+        let (name, parts) = site.series.get_key_value(article.series.as_ref()?)?;
+        let position = parts.iter().position(|part| part.id == article.id)? + 1;
+        Some(SeriesBox {
+            name,
+            path: site.series_path(name),
+            parts: parts.iter().collect(),
+            position,
+        })
+    }
+}
+
+#[derive(Template)]
+#[template(path = "series.html")]
+struct SeriesTemplate<'a> {
+    site: &'a site::Site,
+    base: String,
+    name: &'a str,
+    parts: &'a BTreeSet<Arc<site::Article>>,
+}
+
+#[derive(Template)]
+#[template(path = "notes/note.html")]
+struct NoteTemplate<'a, 'b> {
+    site: &'a site::Site,
+    note: &'b site::Page,
+    base: String,
+    content: String,
+}
+
+#[derive(Template)]
+#[template(path = "pages/page.html")]
+struct PageTemplate<'a, 'b> {
+    site: &'a site::Site,
+    page: &'b site::Page,
+    base: String,
+    content: String,
 }
 
 pub enum Output {
@@ -48,7 +138,7 @@ pub enum Output {
 }
 
 impl Output {
-    pub fn write(&self, path: &str, data: &str, mtime: Option<NaiveDateTime>) -> Result<()> {
+    pub fn write(&self, path: &str, data: &str, mtime: Option<DateTime<Tz>>) -> Result<()> {
         match self {
             Output::Stdout => {
                 let datetime = if let Some(mtime) = mtime {
@@ -56,7 +146,7 @@ impl Output {
                 } else {
                     "".to_string()
                 };
-                stdout().write_all(&format!("{}{}:\n", path, datetime).as_bytes())?;
+                stdout().write_all(format!("{}{}:\n", path, datetime).as_bytes())?;
                 stdout().write_all(data.as_bytes())?;
             }
             Output::Directory(p) => {
@@ -71,10 +161,8 @@ impl Output {
                 file.write_all(data.as_bytes())?;
 
                 if let Some(mtime) = mtime {
-                    let mtime = FileTime::from_unix_time(
-                        mtime.and_utc().timestamp(),
-                        mtime.and_utc().timestamp_subsec_nanos(),
-                    );
+                    let mtime =
+                        FileTime::from_unix_time(mtime.timestamp(), mtime.timestamp_subsec_nanos());
                     set_file_mtime(&p, mtime)?;
                 }
             }
@@ -99,112 +187,152 @@ impl Output {
     }
 }
 
-pub fn generate(site: Rc<site::Site>, output: Output) -> Result<()> {
-    let index = IndexTemplate {
-        site: &site,
-        base: "".to_string(),
-    };
-    let html = index.render().unwrap();
-    output.write("index.html", &html, site.last_update)?;
+type Handler = handlers::ImoHtmlHandler<Error, SyntectHtmlHandler<Error, DefaultHtmlHandler>>;
 
-    for (year, articles) in site.index.iter().rev().skip(1) {
-        let archive = ArchiveTemplate {
-            site: &site,
-            base: "".to_string(),
-            year: year.clone(),
-        };
-        let html = archive.render().unwrap();
-        let last_update = articles
-            .iter()
-            .map(|a| a.updated.unwrap_or(a.published))
-            .max();
-        output.write(&format!("{}.html", year.0), &html, last_update)?;
+fn new_handler(site: &Arc<site::Site>, base: String) -> Handler {
+    handlers::ImoHtmlHandler::new(
+        site.clone(),
+        base,
+        SyntectHtmlHandler::new(DefaultHtmlHandler),
+    )
+}
+
+/// Render `items` on the thread pool, and return results in the same order as `items`.
+fn render_parallel<T, R, F>(
+    site: &Arc<site::Site>,
+    base: &str,
+    items: &[T],
+    render: F,
+) -> Result<Vec<R>>
+where
+    T: Sync,
+    R: Send,
+    F: Fn(&mut Handler, &T) -> Result<R> + Sync,
+{
+    // creating a handler loads syntax definitions, so share one among some items
+    const MIN_CHUNK_SIZE: usize = 16;
+    let chunk_size = items
+        .len()
+        .div_ceil(rayon::current_num_threads())
+        .max(MIN_CHUNK_SIZE);
+    let chunks = items
+        .par_chunks(chunk_size)
+        .map(|chunk| {
+            let mut handler = new_handler(site, base.to_string());
+            chunk
+                .iter()
+                .map(|item| render(&mut handler, item))
+                .collect::<Result<Vec<_>>>()
+        })
+        .collect::<Result<Vec<_>>>()?;
+    Ok(chunks.into_iter().flatten().collect())
+}
+
+pub fn generate(site: Arc<site::Site>, output: Output) -> Result<()> {
+    for item in &site.menu {
+        if site.menu_href(item, "").is_none() && !site.menu_unpublished(item) {
+            site.diagnostics.warn(
+                Kind::BrokenLink,
+                None,
+                format!("menu \"{}\": {} is not published", item.label, item.target),
+            );
+        }
+    }
+
+    write_listing(&site, &output, None)?;
+    for section in &site.sections {
+        write_listing(&site, &output, Some(section))?;
     }
 
     let base = "../../".to_string();
-    let mut handler = handlers::ImoHtmlHandler::new(
-        site.clone(),
-        base.clone(),
-        SyntectHtmlHandler::new(DefaultHtmlHandler),
-    );
 
-    for article in site.articles.values() {
-        let content = article.html(&mut handler)?;
+    // render pages in parallel, then write them in order
+    let mut articles = site.articles.values().collect::<Vec<_>>();
+    if site.include_draft {
+        articles.extend(site.drafts.values());
+    }
+    let links = if site.related > 0 {
+        site.article_links()
+    } else {
+        BTreeMap::new()
+    };
+    let pages = render_parallel(&site, &base, &articles, |handler, article| {
+        handler.set_article(article);
+        let content = article.html(handler)?;
         let tmpl = ArticleTemplate {
             site: &site,
-            article: &article,
+            article,
             base: base.clone(),
-            content: content,
+            content,
+            series: SeriesBox::new(&site, article),
+            related: site.related_articles(article, &links),
         };
-        let html = tmpl.render().unwrap();
-        let mtime = article.updated.unwrap_or(article.published);
-        output.write(&article.path(), &html, Some(mtime))?;
+        let mtime = site.localize(&article.updated.unwrap_or(article.published));
+        Ok((article.path(), tmpl.render().unwrap(), Some(mtime)))
+    })?;
+    for (path, html, mtime) in pages {
+        output.write(&path, &html, mtime)?;
     }
 
-    if site.include_draft {
-        for draft in site.drafts.values() {
-            let content = draft.html(&mut handler)?;
-            let tmpl = ArticleTemplate {
-                site: &site,
-                article: &draft,
-                base: base.clone(),
-                content: content,
-            };
-            let html = tmpl.render().unwrap();
-            let mtime = draft.updated.unwrap_or(draft.published);
-            output.write(&draft.path(), &html, Some(mtime))?;
-        }
+    let notes = site.notes.values().collect::<Vec<_>>();
+    let pages = render_parallel(&site, &base, &notes, |handler, note| {
+        handler.set_page(note);
+        let content = note.html(handler)?;
+        let tmpl = NoteTemplate {
+            site: &site,
+            note,
+            base: base.clone(),
+            content,
+        };
+        Ok((note.path.clone(), tmpl.render().unwrap()))
+    })?;
+    for (path, html) in pages {
+        output.write(&path, &html, None)?;
+    }
+
+    let pages = site.pages.values().collect::<Vec<_>>();
+    let pages = render_parallel(&site, "", &pages, |handler, page| {
+        // pages may be written in sub directories
+        let base = "../".repeat(page.path.matches('/').count());
+        handler.set_base(base.clone());
+        handler.set_page(page);
+        let content = page.html(handler)?;
+        let tmpl = PageTemplate {
+            site: &site,
+            page,
+            base,
+            content,
+        };
+        Ok((page.path.clone(), tmpl.render().unwrap()))
+    })?;
+    for (path, html) in pages {
+        output.write(&path, &html, None)?;
+    }
+
+    for (name, parts) in &site.series {
+        let tmpl = SeriesTemplate {
+            site: &site,
+            base: "../".to_string(),
+            name,
+            parts,
+        };
+        let last_update = parts
+            .iter()
+            .map(|a| a.updated.unwrap_or(a.published))
+            .max()
+            .map(|u| site.localize(&u));
+        output.write(
+            &site.series_path(name),
+            &tmpl.render().unwrap(),
+            last_update,
+        )?;
     }
 
     if site.feed {
-        let site_url = site.url.as_ref().expect("atom feed needs site_url");
-        handler.set_base(site_url.to_string());
-        const FEED_ENTRY_COUNT: usize = 10;
-        let mut recent_entries = Vec::new();
-        for article in site
-            .index
-            .values()
-            .rev()
-            .flat_map(|articles| articles.iter().rev())
-            .take(FEED_ENTRY_COUNT)
-        {
-            let entry_url = site_url.join(&article.path()).unwrap();
-            let published = Local
-                .from_local_datetime(&article.published)
-                .unwrap()
-                .with_timezone(&Utc);
-            let updated = Local
-                .from_local_datetime(&article.updated.unwrap_or(article.published))
-                .unwrap()
-                .with_timezone(&Utc);
-            let content = ContentBuilder::default()
-                .content_type(Some("html".to_string()))
-                .value(Some(article.html(&mut handler)?))
-                .build();
-            let link = LinkBuilder::default().href(entry_url.to_string()).build();
-            let entry = EntryBuilder::default()
-                .title(article.title.clone())
-                .id(&entry_url.to_string())
-                .links(vec![link])
-                .published(Some(published.into()))
-                .updated(updated)
-                .content(Some(content))
-                .build();
-            recent_entries.push(entry);
+        write_feed(&site, &output, None)?;
+        for section in &site.sections {
+            write_feed(&site, &output, Some(section))?;
         }
-        let mut feed = FeedBuilder::default()
-            .title(site.name.clone())
-            .id(site_url.to_string())
-            .entries(recent_entries)
-            .build();
-        if let Some(updated) = site.last_update {
-            let updated = Local
-                .from_local_datetime(&updated)
-                .unwrap()
-                .with_timezone(&Utc);
-            feed.set_updated(updated);
-        }
-        output.write("atom.xml", &feed.to_string(), site.last_update)?;
     }
 
     for filename in StaticFiles::iter() {
@@ -212,9 +340,200 @@ pub fn generate(site: Rc<site::Site>, output: Output) -> Result<()> {
         let mtime = file
             .metadata
             .last_modified()
-            .and_then(|m| DateTime::from_timestamp(m as i64, 0).map(|m| m.naive_local()));
+            .and_then(|m| DateTime::from_timestamp(m as i64, 0))
+            .map(|m| m.with_timezone(&site.time_zone));
         output.write(&filename, std::str::from_utf8(&file.data).unwrap(), mtime)?;
     }
 
     Ok(())
 }
+
+/// Articles listed in the site (or `section`), when they are updated, and the URL prefix.
+fn listing<'a>(
+    site: &'a site::Site,
+    section: Option<&'a site::Section>,
+) -> (&'a Index, Option<NaiveDateTime>, String) {
+    let (index, last_update) = match section {
+        Some(section) => (&section.index, section.last_update),
+        None => (&site.index, site.last_update),
+    };
+    let prefix = section.map(|section| section.prefix()).unwrap_or_default();
+    (index, last_update, prefix)
+}
+
+/// Write the index and archives of the site (or `section`).
+fn write_listing(
+    site: &Arc<site::Site>,
+    output: &Output,
+    section: Option<&site::Section>,
+) -> Result<()> {
+    let (index, last_update, prefix) = listing(site, section);
+    let base = "../".repeat(prefix.matches('/').count());
+
+    let tmpl = IndexTemplate {
+        site,
+        base: base.clone(),
+        section,
+        prefix: prefix.clone(),
+        index,
+    };
+    let html = tmpl.render().unwrap();
+    output.write(
+        &format!("{}index.html", prefix),
+        &html,
+        last_update.map(|u| site.localize(&u)),
+    )?;
+
+    let last_update_of = |articles: &[&Arc<site::Article>]| {
+        articles
+            .iter()
+            .map(|a| a.updated.unwrap_or(a.published))
+            .max()
+            .map(|u| site.localize(&u))
+    };
+    for (year, articles) in index.iter().rev() {
+        let articles = articles.iter().collect::<Vec<_>>();
+        let archive = ArchiveTemplate {
+            site,
+            base: base.clone(),
+            section,
+            prefix: prefix.clone(),
+            year: *year,
+            month: None,
+            articles: articles.clone(),
+            calendar: if site.calendar {
+                calendar(&articles)
+            } else {
+                Vec::new()
+            },
+        };
+        let html = archive.render().unwrap();
+        output.write(
+            &format!("{}{}.html", prefix, year.0),
+            &html,
+            last_update_of(&articles),
+        )?;
+
+        for (month, articles) in site::by_month(articles) {
+            let mtime = last_update_of(&articles);
+            let archive = ArchiveTemplate {
+                site,
+                base: format!("{}../", base),
+                section,
+                prefix: prefix.clone(),
+                year: *year,
+                month: Some(month),
+                articles,
+                calendar: Vec::new(),
+            };
+            let html = archive.render().unwrap();
+            output.write(
+                &format!("{}{}/{:02}.html", prefix, year.0, month),
+                &html,
+                mtime,
+            )?;
+        }
+    }
+
+    let archives = ArchivesTemplate {
+        site,
+        base: base.clone(),
+        section,
+        prefix: prefix.clone(),
+        archives: index
+            .iter()
+            .map(|(year, articles)| (*year, site::by_month(articles)))
+            .collect(),
+    };
+    output.write(
+        &format!("{}archive.html", prefix),
+        &archives.render().unwrap(),
+        last_update.map(|u| site.localize(&u)),
+    )?;
+
+    Ok(())
+}
+
+/// Number of articles published in each month of a year.
+fn calendar(articles: &[&Arc<site::Article>]) -> Vec<CalendarMonth> {
+    (1..=12)
+        .map(|month| {
+            let name = Month::try_from(month as u8).unwrap().name();
+            let count = articles
+                .iter()
+                .filter(|article| article.published.month() == month)
+                .count();
+            CalendarMonth {
+                month,
+                name: &name[..3],
+                count,
+            }
+        })
+        .collect()
+}
+
+/// Write the atom feed of the site (or `section`).
+fn write_feed(
+    site: &Arc<site::Site>,
+    output: &Output,
+    section: Option<&site::Section>,
+) -> Result<()> {
+    let (index, last_update, prefix) = listing(site, section);
+    let site_url = site.url.as_ref().expect("atom feed needs site_url");
+    let mut handler = new_handler(site, site_url.to_string());
+    handler.set_base(site_url.to_string());
+    const FEED_ENTRY_COUNT: usize = 10;
+    let mut recent_entries = Vec::new();
+    for article in index
+        .values()
+        .rev()
+        .flat_map(|articles| articles.iter().rev())
+        .take(FEED_ENTRY_COUNT)
+    {
+        let entry_url = site_url.join(&article.path()).unwrap();
+        handler.set_article(article);
+        let published = site.localize(&article.published).fixed_offset();
+        let updated = site
+            .localize(&article.updated.unwrap_or(article.published))
+            .fixed_offset();
+        let content = ContentBuilder::default()
+            .content_type(Some("html".to_string()))
+            .value(Some(article.html(&mut handler)?))
+            .build();
+        let link = LinkBuilder::default().href(entry_url.to_string()).build();
+        let authors = article
+            .author
+            .iter()
+            .map(|author| PersonBuilder::default().name(author.clone()).build())
+            .collect::<Vec<_>>();
+        let entry = EntryBuilder::default()
+            .title(article.title.clone())
+            .id(entry_url.to_string())
+            .links(vec![link])
+            .authors(authors)
+            .published(Some(published))
+            .updated(updated)
+            .content(Some(content))
+            .build();
+        recent_entries.push(entry);
+    }
+    let title = match section {
+        Some(section) => format!("{} - {}", section.name, site.name),
+        None => site.name.clone(),
+    };
+    let mut feed = FeedBuilder::default()
+        .title(title)
+        .id(site_url.join(&prefix).unwrap().to_string())
+        .entries(recent_entries)
+        .build();
+    if let Some(updated) = last_update {
+        feed.set_updated(site.localize(&updated).fixed_offset());
+    }
+    output.write(
+        &format!("{}atom.xml", prefix),
+        &feed.to_string(),
+        last_update.map(|u| site.localize(&u)),
+    )?;
+
+    Ok(())
+}
@@ -1,24 +1,40 @@
+use indextree::NodeId;
 use orgize::{
     elements::Element,
     export::{HtmlEscape, HtmlHandler},
+    Headline, Org,
 };
+use std::collections::{HashMap, VecDeque};
 use std::io::{Error, Write};
 use std::marker::PhantomData;
-use std::rc::Rc;
+use std::sync::Arc;
 use url::{ParseError, Url};
 
-use crate::site::{get_id, id_to_path, Id, Site};
-use crate::utils::notice;
+use crate::diagnostics::Kind;
+use crate::diagnostics::Location;
+use crate::site::{
+    Article, Heading, Id, Page, Resolved, ResolvedFile, Site, SubSuperscript, UnresolvedLinks,
+};
 
 pub struct ImoHtmlHandler<E: From<Error>, H: HtmlHandler<E>> {
-    site: Rc<Site>,
+    site: Arc<Site>,
     base: String,
+    /// where the article (or page) currently rendering is written
+    location: Option<Location>,
+    /// paths and locations of links not rendered yet, in document order
+    links: VecDeque<(String, Location)>,
+    /// the article currently rendering is a draft
+    from_draft: bool,
+    /// path and headings of the article (or page) currently rendering
+    path: String,
+    headings: Vec<Heading>,
+    sub_superscript: SubSuperscript,
     inner: H,
     e: PhantomData<E>,
 }
 
 impl<E: From<Error>, H: HtmlHandler<E>> ImoHtmlHandler<E, H> {
-    pub fn new(site: Rc<Site>, base: String, inner: H) -> Self {
+    pub fn new(site: Arc<Site>, base: String, inner: H) -> Self {
         ImoHtmlHandler {
             site,
             base,
@@ -29,13 +45,77 @@ impl<E: From<Error>, H: HtmlHandler<E>> ImoHtmlHandler<E, H> {
     pub fn set_base(&mut self, base: String) {
         self.base = base;
     }
+    pub fn set_article(&mut self, article: &Article) {
+        self.location = Some(article.location.clone());
+        self.links = links(
+            &article.org.read().unwrap(),
+            &article.headline,
+            &article.link_locations,
+        );
+        self.from_draft = article.is_draft;
+        self.path = article.path();
+        self.headings = article.headings.clone();
+        self.sub_superscript = article.options.sub_superscript;
+    }
+    pub fn set_page(&mut self, page: &Page) {
+        self.location = Some(page.location.clone());
+        self.links = links(
+            &page.org.read().unwrap(),
+            &page.headline,
+            &page.link_locations,
+        );
+        self.from_draft = false;
+        self.path = page.path.clone();
+        self.headings = page.headings.clone();
+        self.sub_superscript = page.options.sub_superscript;
+    }
+    /// Where the link to `path` is written, or the headline if it is unknown.
+    fn link_location(&mut self, path: &str) -> Option<Location> {
+        match self.links.iter().position(|(p, _)| p == path) {
+            Some(i) => self
+                .links
+                .drain(..=i)
+                .next_back()
+                .map(|(_, location)| location),
+            None => self.location.clone(),
+        }
+    }
+    fn write_article_link<W: Write>(
+        &self,
+        mut w: W,
+        article: &Article,
+        anchor: Option<&str>,
+        desc: &str,
+    ) -> Result<(), Error> {
+        write!(
+            w,
+            "<a{} href=\"{}{}",
+            if article.is_draft {
+                " class=\"draft\""
+            } else {
+                ""
+            },
+            HtmlEscape(&self.base),
+            HtmlEscape(article.path())
+        )?;
+        if let Some(anchor) = anchor {
+            write!(w, "#{}", HtmlEscape(anchor))?;
+        }
+        write!(w, "\">{}</a>", HtmlEscape(desc))
+    }
 }
 
 impl<E: From<Error>, H: HtmlHandler<E>> Default for ImoHtmlHandler<E, H> {
     fn default() -> Self {
         ImoHtmlHandler {
-            site: Rc::new(Site::new("".to_string(), None, false, false)),
+            site: Arc::new(Site::new("".to_string(), None, false, false)),
             base: "".to_string(),
+            location: None,
+            links: VecDeque::new(),
+            from_draft: false,
+            path: "".to_string(),
+            headings: Vec::new(),
+            sub_superscript: SubSuperscript::Off,
             inner: H::default(),
             e: PhantomData,
         }
@@ -45,51 +125,187 @@ impl<E: From<Error>, H: HtmlHandler<E>> Default for ImoHtmlHandler<E, H> {
 impl<E: From<Error>, H: HtmlHandler<E>> HtmlHandler<E> for ImoHtmlHandler<E, H> {
     fn start<W: Write>(&mut self, mut w: W, element: &Element) -> Result<(), E> {
         match element {
-            Element::Title(title) => {
-                if let Some(id) = get_id(title) {
-                    write!(
-                        w,
-                        "<h{} id=\"{}\">",
-                        if title.level <= 6 { title.level } else { 6 },
-                        HtmlEscape(id.to_string())
-                    )?;
-                } else {
-                    write!(w, "<h{}>", if title.level <= 6 { title.level } else { 6 })?;
-                }
-            }
             Element::Link(link) => {
                 if link.path.starts_with("id:") {
                     let id = Id::new(link.path[3..].to_string());
-                    if self.site.articles.contains_key(&id) {
-                        write!(
-                            w,
-                            "<a href=\"{}{}\">{}</a>",
-                            HtmlEscape(&self.base),
-                            HtmlEscape(id_to_path(&id)),
-                            HtmlEscape(link.desc.as_ref().unwrap_or(&link.path))
-                        )?;
-                    } else if let Some(article_id) = self.site.subid_to_articleid_map.get(&id) {
+                    let desc = link.desc.as_ref().unwrap_or(&link.path);
+                    let from_draft = self.from_draft;
+                    let location = self.link_location(&link.path);
+                    match self.site.resolve_id(&id) {
+                        Resolved::Article(article, anchor) => {
+                            if article.is_draft && !from_draft {
+                                self.site.diagnostics.warn(
+                                    Kind::LinkToDraft,
+                                    location,
+                                    format!("id:{} is a draft", id),
+                                );
+                            }
+                            let anchor = anchor.map(|anchor| anchor.to_string());
+                            self.write_article_link(w, article, anchor.as_deref(), desc)?;
+                        }
+                        Resolved::ExcludedDraft => {
+                            self.site.diagnostics.warn(
+                                Kind::LinkToDraft,
+                                location,
+                                format!("id:{} is a draft", id),
+                            );
+                            write!(w, "{}", HtmlEscape(desc))?;
+                        }
+                        Resolved::Embargoed => {
+                            self.site.diagnostics.warn(
+                                Kind::FutureArticle,
+                                location,
+                                format!("id:{} is scheduled in the future", id),
+                            );
+                            write!(w, "{}", HtmlEscape(desc))?;
+                        }
+                        Resolved::Page(page, anchor) => {
+                            write!(
+                                w,
+                                "<a href=\"{}{}",
+                                HtmlEscape(&self.base),
+                                HtmlEscape(&page.path)
+                            )?;
+                            if let Some(anchor) = anchor {
+                                write!(w, "#{}", HtmlEscape(anchor.to_string()))?;
+                            }
+                            write!(w, "\">{}</a>", HtmlEscape(desc))?;
+                        }
+                        Resolved::Note(note) => {
+                            write!(
+                                w,
+                                "<a class=\"note\" href=\"{}{}\">{}</a>",
+                                HtmlEscape(&self.base),
+                                HtmlEscape(&note.path),
+                                HtmlEscape(desc)
+                            )?;
+                        }
+                        Resolved::Unpublished => match &self.site.unresolved_links {
+                            UnresolvedLinks::Url(template) => {
+                                write!(
+                                    w,
+                                    "<a href=\"{}\">{}</a>",
+                                    HtmlEscape(template.replace("{id}", &id.to_string())),
+                                    HtmlEscape(desc)
+                                )?;
+                            }
+                            UnresolvedLinks::Span => {
+                                write!(
+                                    w,
+                                    "<span class=\"unpublished\" title=\"not published\">{}</span>",
+                                    HtmlEscape(desc)
+                                )?;
+                            }
+                            UnresolvedLinks::Text | UnresolvedLinks::Note => {
+                                self.site.diagnostics.warn(
+                                    Kind::BrokenLink,
+                                    location,
+                                    format!("id:{} is not published", id),
+                                );
+                                write!(w, "{}", HtmlEscape(desc))?;
+                            }
+                        },
+                        Resolved::NotFound => {
+                            self.site.diagnostics.warn(
+                                Kind::BrokenLink,
+                                location,
+                                format!("id:{} not found", id),
+                            );
+                            if let UnresolvedLinks::Url(template) = &self.site.unresolved_links {
+                                write!(
+                                    w,
+                                    "<a href=\"{}\">{}</a>",
+                                    HtmlEscape(template.replace("{id}", &id.to_string())),
+                                    HtmlEscape(desc)
+                                )?;
+                            } else {
+                                write!(w, "{}", HtmlEscape(desc))?;
+                            }
+                        }
+                    }
+                } else if link.path.starts_with('*') || link.path.starts_with('#') {
+                    let location = self.link_location(&link.path);
+                    let desc = link
+                        .desc
+                        .as_deref()
+                        .unwrap_or_else(|| link.path.trim_start_matches('*'));
+                    // the same article first, then whole site
+                    if let Some(heading) = self.headings.iter().find(|h| h.matches(&link.path)) {
                         write!(
                             w,
                             "<a href=\"{}{}#{}\">{}</a>",
                             HtmlEscape(&self.base),
-                            HtmlEscape(id_to_path(&article_id)),
-                            HtmlEscape(&id.to_string()),
-                            HtmlEscape(link.desc.as_ref().unwrap_or(&link.path))
+                            HtmlEscape(&self.path),
+                            HtmlEscape(&heading.anchor),
+                            HtmlEscape(desc)
                         )?;
+                    } else if let Some((article, heading)) = self.site.resolve_heading(&link.path) {
+                        if article.is_draft && !self.from_draft {
+                            self.site.diagnostics.warn(
+                                Kind::LinkToDraft,
+                                location.clone(),
+                                format!("{} is in a draft", link.path),
+                            );
+                        }
+                        self.write_article_link(w, article, Some(&heading.anchor), desc)?;
                     } else {
-                        notice(&format!("id:{} not found", id.to_string()));
-                        write!(
-                            w,
-                            "{}",
-                            HtmlEscape(link.desc.as_ref().unwrap_or(&link.path))
-                        )?;
+                        self.site.diagnostics.warn(
+                            Kind::BrokenLink,
+                            location.clone(),
+                            format!("{} not found", link.path),
+                        );
+                        write!(w, "{}", HtmlEscape(desc))?;
+                    }
+                } else if let Some(path) = link.path.strip_prefix("file:") {
+                    let desc = link.desc.as_ref().unwrap_or(&link.path);
+                    let location = self.link_location(&link.path);
+                    let from = location.as_ref().and_then(|l| l.file.as_deref());
+                    match self.site.resolve_file(from, path) {
+                        ResolvedFile::Article(article, heading) => {
+                            if article.is_draft && !self.from_draft {
+                                self.site.diagnostics.warn(
+                                    Kind::LinkToDraft,
+                                    location.clone(),
+                                    format!("{} is a draft", link.path),
+                                );
+                            }
+                            let anchor = heading.map(|heading| heading.anchor.as_str());
+                            self.write_article_link(w, article, anchor, desc)?;
+                        }
+                        ResolvedFile::Ambiguous(n) => {
+                            self.site.diagnostics.warn(
+                                Kind::BrokenLink,
+                                location.clone(),
+                                format!(
+                                    "{} has {} articles, specify one with \"::*Heading\"",
+                                    link.path, n
+                                ),
+                            );
+                            write!(w, "{}", HtmlEscape(desc))?;
+                        }
+                        ResolvedFile::Unpublished => {
+                            self.site.diagnostics.warn(
+                                Kind::BrokenLink,
+                                location.clone(),
+                                format!("{} is not published", link.path),
+                            );
+                            write!(w, "{}", HtmlEscape(desc))?;
+                        }
+                        ResolvedFile::NotFound => {
+                            self.site.diagnostics.warn(
+                                Kind::BrokenLink,
+                                location.clone(),
+                                format!("{} not found", link.path),
+                            );
+                            write!(w, "{}", HtmlEscape(desc))?;
+                        }
+                        ResolvedFile::NotInput => {
+                            // remove "file:" prefix and re-start
+                            let mut fixed = link.clone();
+                            fixed.path = path.to_string().into();
+                            self.start(w, &Element::Link(fixed))?;
+                        }
                     }
-                } else if link.path.starts_with("file:") {
-                    // remove "file:" prefix and re-start
-                    let mut fixed = link.clone();
-                    fixed.path = link.path[5..].into();
-                    self.start(w, &Element::Link(fixed))?;
                 } else {
                     let link =
                         if let Err(ParseError::RelativeUrlWithoutBase) = Url::parse(&link.path) {
@@ -100,30 +316,29 @@ impl<E: From<Error>, H: HtmlHandler<E>> HtmlHandler<E> for ImoHtmlHandler<E, H>
                         } else {
                             link.clone()
                         };
-                    let is_image = link
-                        .path
-                        .rsplit("/")
-                        .next()
-                        .and_then(|may_filename| may_filename.rsplit_once("."))
-                        .and_then(|(_, ext)| {
-                            if ["jpeg", "jpg", "png", "svg"].contains(&ext) {
-                                Some(())
-                            } else {
-                                None
-                            }
-                        })
-                        .is_some();
-                    if is_image {
-                        write!(
-                            w,
-                            "<a href=\"{path}\"><img src=\"{path}\"></a>",
-                            path = HtmlEscape(&link.path),
-                        )?;
+                    if is_image(&link.path) {
+                        if let Some(desc) = &link.desc {
+                            write!(
+                                w,
+                                "<a href=\"{path}\"><img src=\"{path}\" alt=\"{alt}\"></a>",
+                                path = HtmlEscape(&link.path),
+                                alt = HtmlEscape(desc),
+                            )?;
+                        } else {
+                            write!(
+                                w,
+                                "<a href=\"{path}\"><img src=\"{path}\"></a>",
+                                path = HtmlEscape(&link.path),
+                            )?;
+                        }
                     } else {
                         self.inner.start(w, &Element::Link(link))?;
                     }
                 }
             }
+            Element::Text { value } if self.sub_superscript != SubSuperscript::Off => {
+                write_sub_superscript(w, value, self.sub_superscript)?;
+            }
             Element::FnDef(fn_def) => {
                 write!(w, "<small>[{}]</small>", fn_def.label)?;
             }
@@ -135,9 +350,80 @@ impl<E: From<Error>, H: HtmlHandler<E>> HtmlHandler<E> for ImoHtmlHandler<E, H>
         Ok(())
     }
     fn end<W: Write>(&mut self, w: W, element: &Element) -> Result<(), E> {
-        match element {
-            _ => self.inner.end(w, element)?,
+        self.inner.end(w, element)
+    }
+}
+
+/// Paths and locations of links in `headline`, in the order they are rendered.
+fn links(
+    org: &Org,
+    headline: &Headline,
+    locations: &HashMap<NodeId, Location>,
+) -> VecDeque<(String, Location)> {
+    headline
+        .headline_node()
+        .descendants(org.arena())
+        .filter_map(|node| match &org[node] {
+            Element::Link(link) => Some((link.path.to_string(), locations.get(&node)?.clone())),
+            _ => None,
+        })
+        .collect()
+}
+
+pub fn is_image(path: &str) -> bool {
+    path.rsplit('/')
+        .next()
+        .and_then(|may_filename| may_filename.rsplit_once('.'))
+        .and_then(|(_, ext)| {
+            if ["jpeg", "jpg", "png", "svg"].contains(&ext) {
+                Some(())
+            } else {
+                None
+            }
+        })
+        .is_some()
+}
+
+/// Write `text` rendering `a^b` and `a_b` (or only `a^{b}` and `a_{b}`) as superscript and
+/// subscript.
+fn write_sub_superscript<W: Write>(
+    mut w: W,
+    text: &str,
+    mode: SubSuperscript,
+) -> Result<(), Error> {
+    // start of the text not written yet
+    let mut start = 0;
+    let mut prev: Option<char> = None;
+    let mut chars = text.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        if (c == '^' || c == '_') && prev.is_some_and(|p| !p.is_whitespace()) {
+            let after = &text[i + 1..];
+            // (script, length of it in `after`)
+            let script = if let Some(braced) = after.strip_prefix('{') {
+                braced.find('}').map(|end| (&braced[..end], end + 2))
+            } else if mode == SubSuperscript::On {
+                let end = after
+                    .find(|c: char| !c.is_alphanumeric())
+                    .unwrap_or(after.len());
+                Some((&after[..end], end)).filter(|(script, _)| !script.is_empty())
+            } else {
+                None
+            };
+            if let Some((script, len)) = script {
+                let tag = if c == '^' { "sup" } else { "sub" };
+                write!(
+                    w,
+                    "{}<{tag}>{}</{tag}>",
+                    HtmlEscape(&text[start..i]),
+                    HtmlEscape(script)
+                )?;
+                start = i + 1 + len;
+                while chars.next_if(|(j, _)| *j < start).is_some() {}
+                prev = script.chars().last();
+                continue;
+            }
         }
-        Ok(())
+        prev = Some(c);
     }
+    write!(w, "{}", HtmlEscape(&text[start..]))
 }
@@ -0,0 +1,338 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::diagnostics::{Diagnostic, Kind, Location, Severity};
+
+/// Org data with `#+INCLUDE:` and `#+SETUPFILE:` expanded.
+#[derive(Default)]
+pub struct Expanded {
+    pub text: String,
+    /// where each line of `text` comes from
+    pub sources: Vec<Location>,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+impl Expanded {
+    fn push_line(&mut self, line: &str, source: Location) {
+        self.text.push_str(line);
+        self.text.push('\n');
+        self.sources.push(source);
+    }
+    fn warn(&mut self, kind: Kind, location: Location, message: String) {
+        self.diagnostics.push(Diagnostic {
+            severity: Severity::Warning,
+            kind,
+            location: Some(location),
+            message,
+        });
+    }
+}
+
+/// Expand `#+INCLUDE:` and `#+SETUPFILE:` in `data` read from `file`.
+///
+/// Included files are looked up relative to the including file (or the current directory).
+pub fn expand(file: Option<&Path>, data: &str) -> Expanded {
+    let mut expanded = Expanded::default();
+    let mut stack = file
+        .map(|file| file.canonicalize().unwrap_or_else(|_| file.to_path_buf()))
+        .into_iter()
+        .collect();
+    expand_into(&mut expanded, &mut stack, file, data, 0, false);
+    expanded
+}
+
+/// How an included file is inserted.
+enum Block {
+    /// as org text
+    None,
+    /// `src LANG`
+    Src(String),
+    /// `example`
+    Example,
+    /// `export BACKEND`
+    Export(String),
+}
+
+struct Include {
+    path: String,
+    block: Block,
+    /// `:lines "A-B"` (1-origin, B is excluded)
+    lines: (Option<usize>, Option<usize>),
+    minlevel: Option<usize>,
+}
+
+/// `stack` is files being expanded, to detect cycles. `data` starts at line `offset + 1` of `file`.
+/// Only keywords are taken from `data` when `setup_only` (for `#+SETUPFILE:`).
+fn expand_into(
+    expanded: &mut Expanded,
+    stack: &mut Vec<PathBuf>,
+    file: Option<&Path>,
+    data: &str,
+    offset: usize,
+    setup_only: bool,
+) {
+    let dir = file.and_then(Path::parent).unwrap_or(Path::new(""));
+    let mut in_block = false;
+    // level of the last headline, included headlines become its children
+    let mut level = 0;
+    for (i, line) in data.lines().enumerate() {
+        let location = Location {
+            file: file.map(Path::to_path_buf),
+            line: offset + i + 1,
+        };
+        let trimmed = line.trim_start();
+        let upper = trimmed.to_ascii_uppercase();
+        if upper.starts_with("#+BEGIN_") {
+            in_block = true;
+        } else if upper.starts_with("#+END_") {
+            in_block = false;
+        }
+        if !in_block {
+            if let Some(l) = headline_level(line) {
+                level = l;
+            }
+            if upper.starts_with("#+INCLUDE:") {
+                if !setup_only {
+                    let args = &trimmed["#+INCLUDE:".len()..];
+                    include(expanded, stack, dir, args, level, location);
+                }
+                continue;
+            }
+            if upper.starts_with("#+SETUPFILE:") {
+                let args = &trimmed["#+SETUPFILE:".len()..];
+                setup_file(expanded, stack, dir, args, location);
+                continue;
+            }
+        }
+        if !setup_only || (upper.starts_with("#+") && !in_block) {
+            expanded.push_line(line, location);
+        }
+    }
+}
+
+fn include(
+    expanded: &mut Expanded,
+    stack: &mut Vec<PathBuf>,
+    dir: &Path,
+    args: &str,
+    level: usize,
+    location: Location,
+) {
+    let include = match parse_include(args) {
+        Ok(include) => include,
+        Err(message) => {
+            expanded.warn(
+                Kind::InvalidInclude,
+                location,
+                format!("invalid #+INCLUDE: {}", message),
+            );
+            return;
+        }
+    };
+    let Some((path, data)) = read(expanded, stack, dir, &include.path, &location) else {
+        return;
+    };
+    let (start, end) = include.lines;
+    let lines = data
+        .lines()
+        .enumerate()
+        .skip(start.unwrap_or(1).saturating_sub(1))
+        .take_while(|(i, _)| end.is_none_or(|end| i + 1 < end))
+        .collect::<Vec<_>>();
+    let source = |i: usize| Location {
+        file: Some(path.clone()),
+        line: i + 1,
+    };
+
+    let (begin, end) = match &include.block {
+        Block::None => {
+            let text = lines
+                .iter()
+                .map(|(_, line)| format!("{}\n", line))
+                .collect::<String>();
+            let offset = lines.first().map(|(i, _)| *i).unwrap_or(0);
+            let mut included = Expanded::default();
+            stack.push(path.canonicalize().unwrap_or_else(|_| path.clone()));
+            expand_into(&mut included, stack, Some(&path), &text, offset, false);
+            stack.pop();
+            let minlevel = include.minlevel.unwrap_or(level + 1);
+            let shift = included
+                .text
+                .lines()
+                .filter_map(headline_level)
+                .min()
+                .map(|min| minlevel as isize - min as isize)
+                .unwrap_or(0);
+            for (line, source) in included.text.lines().zip(included.sources) {
+                expanded.push_line(&shift_headline(line, shift), source);
+            }
+            expanded.diagnostics.extend(included.diagnostics);
+            return;
+        }
+        Block::Src(lang) => (format!("#+begin_src {}", lang), "#+end_src"),
+        Block::Example => ("#+begin_example".to_string(), "#+end_example"),
+        Block::Export(backend) => (format!("#+begin_export {}", backend), "#+end_export"),
+    };
+    expanded.push_line(&begin, location.clone());
+    for (i, line) in lines {
+        // escape lines which would end the block or be parsed as a headline
+        let rest = line.trim_start();
+        if line.starts_with('*') || rest.starts_with("#+") {
+            let indent = &line[..line.len() - rest.len()];
+            expanded.push_line(&format!("{},{}", indent, rest), source(i));
+        } else {
+            expanded.push_line(line, source(i));
+        }
+    }
+    expanded.push_line(end, location);
+}
+
+fn setup_file(
+    expanded: &mut Expanded,
+    stack: &mut Vec<PathBuf>,
+    dir: &Path,
+    args: &str,
+    location: Location,
+) {
+    let name = args.trim().trim_matches('"');
+    if name.starts_with("http://") || name.starts_with("https://") {
+        expanded.warn(
+            Kind::InvalidInclude,
+            location,
+            format!("remote #+SETUPFILE: is not supported: {}", name),
+        );
+        return;
+    }
+    let Some((path, data)) = read(expanded, stack, dir, name, &location) else {
+        return;
+    };
+    stack.push(path.canonicalize().unwrap_or_else(|_| path.clone()));
+    expand_into(expanded, stack, Some(&path), &data, 0, true);
+    stack.pop();
+}
+
+/// Read included file `name`, or report why it couldn't be.
+fn read(
+    expanded: &mut Expanded,
+    stack: &[PathBuf],
+    dir: &Path,
+    name: &str,
+    location: &Location,
+) -> Option<(PathBuf, String)> {
+    let path = dir.join(name);
+    let canonical = path.canonicalize().unwrap_or_else(|_| path.clone());
+    if stack.contains(&canonical) {
+        expanded.warn(
+            Kind::IncludeCycle,
+            location.clone(),
+            format!("{} includes itself", name),
+        );
+        return None;
+    }
+    match fs::read_to_string(&path) {
+        Ok(data) => Some((path, data)),
+        Err(e) => {
+            expanded.warn(
+                Kind::MissingFile,
+                location.clone(),
+                format!("couldn't include {}: {}", name, e),
+            );
+            None
+        }
+    }
+}
+
+fn parse_include(args: &str) -> Result<Include, String> {
+    let mut words = split_words(args).into_iter();
+    let path = words.next().ok_or("file name is missing")?;
+    if path.contains("::") {
+        return Err(format!("search option is not supported: {}", path));
+    }
+    let mut include = Include {
+        path,
+        block: Block::None,
+        lines: (None, None),
+        minlevel: None,
+    };
+    while let Some(word) = words.next() {
+        match word.to_ascii_lowercase().as_str() {
+            "src" => include.block = Block::Src(words.next().unwrap_or_default()),
+            "example" => include.block = Block::Example,
+            "export" => include.block = Block::Export(words.next().unwrap_or_default()),
+            ":lines" => {
+                let range = words.next().ok_or(":lines needs a range")?;
+                let (start, end) = range
+                    .split_once('-')
+                    .ok_or_else(|| format!("invalid :lines: {}", range))?;
+                let parse = |n: &str| {
+                    if n.is_empty() {
+                        Ok(None)
+                    } else {
+                        n.parse()
+                            .map(Some)
+                            .map_err(|_| format!("invalid :lines: {}", range))
+                    }
+                };
+                include.lines = (parse(start)?, parse(end)?);
+            }
+            ":minlevel" => {
+                let level = words.next().ok_or(":minlevel needs a number")?;
+                include.minlevel = Some(
+                    level
+                        .parse()
+                        .map_err(|_| format!("invalid :minlevel: {}", level))?,
+                );
+            }
+            // other options (e.g. :only-contents) are ignored
+            _ => {}
+        }
+    }
+    Ok(include)
+}
+
+/// Split `s` by whitespaces, treating "double quoted" words as one.
+fn split_words(s: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut chars = s.trim().chars().peekable();
+    while let Some(c) = chars.next() {
+        if c.is_whitespace() {
+            continue;
+        }
+        let mut word = String::new();
+        if c == '"' {
+            for c in chars.by_ref() {
+                if c == '"' {
+                    break;
+                }
+                word.push(c);
+            }
+        } else {
+            word.push(c);
+            while let Some(c) = chars.next_if(|c| !c.is_whitespace()) {
+                word.push(c);
+            }
+        }
+        words.push(word);
+    }
+    words
+}
+
+fn headline_level(line: &str) -> Option<usize> {
+    let rest = line.trim_start_matches('*');
+    let level = line.len() - rest.len();
+    if level > 0 && (rest.is_empty() || rest.starts_with([' ', '\t'])) {
+        Some(level)
+    } else {
+        None
+    }
+}
+
+fn shift_headline(line: &str, shift: isize) -> String {
+    match headline_level(line) {
+        Some(level) if shift != 0 => {
+            let level = (level as isize + shift).max(1) as usize;
+            format!("{}{}", "*".repeat(level), line.trim_start_matches('*'))
+        }
+        _ => line.to_string(),
+    }
+}
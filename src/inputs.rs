@@ -0,0 +1,95 @@
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use ignore::overrides::{Override, OverrideBuilder};
+use ignore::WalkBuilder;
+use std::collections::BTreeSet;
+use std::io::{Error, ErrorKind, Result};
+use std::path::{Path, PathBuf};
+
+/// Expand `inputs` (org files, directories or glob patterns) into org files.
+///
+/// Directories are searched recursively for `*.org` files, skipping hidden files and files
+/// ignored by `.gitignore` (or `.ignore`). Files matching one of `exclude` (gitignore-style
+/// patterns) are skipped, except files given explicitly.
+pub fn collect(inputs: &[String], exclude: &[String]) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for input in inputs {
+        let path = Path::new(input);
+        if path.is_dir() {
+            let exclude = excludes(path, exclude)?;
+            let walk = WalkBuilder::new(path)
+                .require_git(false)
+                .overrides(exclude)
+                .sort_by_file_name(|a, b| a.cmp(b))
+                .build();
+            for entry in walk {
+                let entry = entry.map_err(Error::other)?;
+                if entry.file_type().is_some_and(|t| t.is_file()) && is_org(entry.path()) {
+                    files.push(entry.into_path());
+                }
+            }
+        } else if path.exists() {
+            files.push(path.to_path_buf());
+        } else if input.contains(['*', '?', '[']) {
+            let root = glob_root(input);
+            let exclude = glob_excludes(&root, exclude)?;
+            let paths = glob::glob(input)
+                .map_err(|e| Error::new(ErrorKind::InvalidInput, format!("{}: {}", input, e)))?;
+            for path in paths {
+                let path = path.map_err(Error::other)?;
+                // check parent directories too, so that "drafts/" excludes files under it
+                let rel = path.strip_prefix(&root).unwrap_or(&path);
+                let excluded =
+                    !rel.has_root() && exclude.matched_path_or_any_parents(rel, false).is_ignore();
+                if path.is_file() && !excluded {
+                    files.push(path);
+                }
+            }
+        } else {
+            return Err(Error::new(
+                ErrorKind::NotFound,
+                format!("{}: no such file or directory", input),
+            ));
+        }
+    }
+    // the same file may be matched by more than one input
+    let mut seen = BTreeSet::new();
+    files.retain(|file| seen.insert(file.canonicalize().unwrap_or_else(|_| file.clone())));
+    Ok(files)
+}
+
+fn excludes(root: &Path, patterns: &[String]) -> Result<Override> {
+    let mut builder = OverrideBuilder::new(root);
+    for pattern in patterns {
+        // overrides are whitelist by default, "!" makes a pattern an ignore one
+        builder
+            .add(&format!("!{}", pattern))
+            .map_err(|e| Error::new(ErrorKind::InvalidInput, e))?;
+    }
+    builder
+        .build()
+        .map_err(|e| Error::new(ErrorKind::InvalidInput, e))
+}
+
+/// The longest leading part of glob `pattern` without wildcards.
+fn glob_root(pattern: &str) -> PathBuf {
+    Path::new(pattern)
+        .components()
+        .take_while(|c| !c.as_os_str().to_string_lossy().contains(['*', '?', '[']))
+        .collect()
+}
+
+fn glob_excludes(root: &Path, patterns: &[String]) -> Result<Gitignore> {
+    let mut builder = GitignoreBuilder::new(root);
+    for pattern in patterns {
+        builder
+            .add_line(None, pattern)
+            .map_err(|e| Error::new(ErrorKind::InvalidInput, e))?;
+    }
+    builder
+        .build()
+        .map_err(|e| Error::new(ErrorKind::InvalidInput, e))
+}
+
+fn is_org(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext == "org")
+}
@@ -2,25 +2,54 @@ use std::fs;
 use std::io::Read;
 use std::io::Result;
 use std::path::PathBuf;
-use std::rc::Rc;
+use std::process::ExitCode;
+use std::sync::Arc;
 use std::time::Instant;
 use url::Url;
 
-use clap::Parser;
+use chrono_tz::Tz;
 
+use clap::{Parser, Subcommand};
+use rayon::prelude::*;
+
+mod check;
+mod diagnostics;
 mod generator;
 mod handlers;
+mod include;
+mod inputs;
 mod site;
 #[cfg(test)]
 mod tests;
-mod utils;
 
 #[derive(Parser, Debug)]
-#[clap(author, version, about, long_about = None)]
+#[clap(
+    author,
+    version,
+    about,
+    long_about = None,
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
+)]
+struct Cli {
+    #[clap(subcommand)]
+    command: Option<Command>,
+
+    #[clap(flatten)]
+    args: Args,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// validate org files (links, IDs, publish dates, ...) instead of generating site
+    Check(CheckArgs),
+}
+
+#[derive(clap::Args, Debug)]
 struct Args {
     /// site name
-    #[clap(short = 'n', long)]
-    site_name: String,
+    #[clap(short = 'n', long, required = true)]
+    site_name: Option<String>,
 
     /// site URL (used by atom feed)
     #[clap(short = 'u', long)]
@@ -38,23 +67,196 @@ struct Args {
     #[clap(short, long)]
     draft: bool,
 
-    /// org files
+    /// generate pages of articles published in the future
+    /// (they are not listed in index, archives and feeds)
+    #[clap(long)]
+    future: bool,
+
+    /// render notes taken in LOGBOOK as a changelog at the bottom of articles
+    #[clap(long)]
+    changelog: bool,
+
+    /// add an entry to the navigation menu in the site header (can be repeated),
+    /// TARGET is "id:ID", URL or path relative to the site root
+    #[clap(long, value_name = "LABEL=TARGET")]
+    menu: Vec<site::MenuItem>,
+
+    /// show a calendar (12 months with the number of articles) on archive pages of years
+    #[clap(long)]
+    calendar: bool,
+
+    /// show up to N related articles (sharing tags or linked by id: links) on article pages
+    #[clap(long, value_name = "N", default_value_t = 0)]
+    related: usize,
+
+    /// exit with non-zero status if there are any warnings
+    #[clap(long)]
+    strict: bool,
+
+    #[clap(flatten)]
+    load: LoadArgs,
+}
+
+#[derive(clap::Args, Debug)]
+struct CheckArgs {
+    #[clap(flatten)]
+    load: LoadArgs,
+}
+
+// options used to load org files
+#[derive(clap::Args, Debug)]
+struct LoadArgs {
+    /// time zone of timestamps written in org files (IANA name, e.g. "Asia/Tokyo")
+    #[clap(short = 't', long, default_value = "UTC")]
+    time_zone: Tz,
+
+    /// where to find publish date of articles, in order of precedence
+    /// ("scheduled", "closed", "deadline" or property name like "PUBLISHED")
+    #[clap(long, value_delimiter = ',', default_value = "scheduled")]
+    date_source: Vec<site::DateSource>,
+
+    /// kinds of LOGBOOK entries which mark an article as updated ("note", "state" or "other")
+    #[clap(long, value_delimiter = ',', default_value = "note,state,other")]
+    updated_from: Vec<site::LogbookEntryKind>,
+
+    /// how to render links to headlines which are not published
+    /// ("text", "span", "note" or "url:TEMPLATE" where "{id}" in TEMPLATE is replaced with the ID)
+    #[clap(long, default_value = "text")]
+    unresolved_links: site::UnresolvedLinks,
+
+    /// which headlines become articles: ones tagged "blog" ("own"),
+    /// or children of them ("children")
+    #[clap(long, default_value = "own")]
+    blog_tag_mode: site::BlogTagMode,
+
+    /// tags marking articles, or a tag match like org agenda (e.g. "blog+team-internal|notes")
+    #[clap(long, value_name = "MATCH", default_value = "blog")]
+    publish_tags: site::TagMatch,
+
+    /// tags marking drafts, or a tag match like org agenda
+    #[clap(long, value_name = "MATCH", default_value = "draft")]
+    draft_tags: site::TagMatch,
+
+    /// tags marking standalone pages (which are not dated), or a tag match like org agenda
+    #[clap(long, value_name = "MATCH", default_value = "page")]
+    page_tags: site::TagMatch,
+
+    /// section which has its own index, archives and feed under NAME/, listing articles
+    /// matching MATCH (or tagged NAME) or having "SECTION: NAME" property (can be repeated)
+    #[clap(long, value_name = "NAME[=MATCH]")]
+    section: Vec<site::Section>,
+
+    /// tags which are not inherited by sub-headlines
+    #[clap(long, value_delimiter = ',', value_name = "TAGS")]
+    tags_exclude_from_inheritance: Vec<String>,
+
+    /// tags which exclude headlines (and their sub-headlines) from export
+    #[clap(
+        long,
+        value_delimiter = ',',
+        value_name = "TAGS",
+        default_value = "PRIVATE,noexport"
+    )]
+    exclude_tags: Vec<String>,
+
+    /// don't fail when the same ID is used by more than one headline
+    #[clap(long)]
+    allow_duplicate_ids: bool,
+
+    /// format of warnings ("text" or "json")
+    #[clap(long, default_value = "text")]
+    diagnostics_format: diagnostics::Format,
+
+    /// skip files matching PATTERN (gitignore-style) in input directories and glob patterns
+    #[clap(long, value_name = "PATTERN")]
+    exclude: Vec<String>,
+
+    /// org files, directories (searched recursively for *.org) or glob patterns
     #[clap(required = true)]
     files: Vec<String>,
 }
 
-fn main() -> Result<()> {
-    let args = Args::parse();
+fn main() -> Result<ExitCode> {
+    let cli = Cli::parse();
 
-    let start = Instant::now();
+    match cli.command {
+        Some(Command::Check(args)) => check(args),
+        None => generate(cli.args),
+    }
+}
 
-    let mut site = site::Site::new(args.site_name, args.site_url, args.feed, args.draft);
-    for fname in args.files {
-        let mut f = fs::File::open(fname)?;
-        let mut buf = String::new();
-        f.read_to_string(&mut buf)?;
+fn load(site: &mut site::Site, args: LoadArgs) -> Result<()> {
+    site.time_zone = args.time_zone;
+    site.allow_duplicate_ids = args.allow_duplicate_ids;
+    site.date_sources = args.date_source;
+    site.updated_from = args.updated_from;
+    site.unresolved_links = args.unresolved_links;
+    site.blog_tag_mode = args.blog_tag_mode;
+    site.publish_tags = args.publish_tags;
+    site.draft_tags = args.draft_tags;
+    site.page_tags = args.page_tags;
+    site.sections = args.section;
+    site.tags_exclude_from_inheritance = args.tags_exclude_from_inheritance;
+    site.exclude_tags = args.exclude_tags;
+    // read and parse files in parallel, then load them in the order given
+    let org_files = inputs::collect(&args.files, &args.exclude)?
+        .into_par_iter()
+        .map(|fname| {
+            let mut f = fs::File::open(&fname)?;
+            let mut buf = String::new();
+            f.read_to_string(&mut buf)?;
 
-        site.load_org_data(buf);
+            Ok(site::OrgFile::parse(Some(fname), buf))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    for org_file in org_files {
+        site.load_org_file(org_file);
+    }
+    site.load_notes();
+    Ok(())
+}
+
+fn check(args: CheckArgs) -> Result<ExitCode> {
+    let diagnostics_format = args.load.diagnostics_format;
+
+    // load everything to check them
+    let mut site = site::Site::new("".to_string(), None, false, true);
+    site.include_future = true;
+    load(&mut site, args.load)?;
+
+    check::check(&site);
+
+    match diagnostics_format {
+        // the site is not written, so stdout is free for JSON
+        diagnostics::Format::Json => println!("{}", site.diagnostics.to_json()),
+        diagnostics::Format::Text => site.diagnostics.report(diagnostics_format),
+    }
+    if site.diagnostics.is_empty() {
+        Ok(ExitCode::SUCCESS)
+    } else {
+        Ok(ExitCode::FAILURE)
+    }
+}
+
+fn generate(args: Args) -> Result<ExitCode> {
+    let start = Instant::now();
+    let diagnostics_format = args.load.diagnostics_format;
+
+    let mut site = site::Site::new(
+        args.site_name.unwrap_or_default(),
+        args.site_url,
+        args.feed,
+        args.draft,
+    );
+    site.include_future = args.future;
+    site.changelog = args.changelog;
+    site.menu = args.menu;
+    site.calendar = args.calendar;
+    site.related = args.related;
+    load(&mut site, args.load)?;
+    if site.diagnostics.count(diagnostics::Severity::Error) > 0 {
+        site.diagnostics.report(diagnostics_format);
+        return Ok(ExitCode::FAILURE);
     }
 
     let output = if let Some(path) = args.output {
@@ -63,54 +265,58 @@ fn main() -> Result<()> {
         generator::Output::Stdout
     };
 
-    let site = Rc::new(site);
+    let site = Arc::new(site);
 
     generator::generate(site.clone(), output)?;
 
     let duration = start.elapsed();
-    let articles = site.articles.len();
-    let drafts = site.drafts.len();
-    let indices = site.index.len();
-    let statics = generator::StaticFiles::iter().count();
-    if site.feed && site.include_draft {
-        eprintln!(
-            "generate {} files ({} articles, {} drafts, {} indices, 1 feed, {} static files) in {:.2}s",
-            articles + drafts + indices + 1 + statics,
-            articles,
-            drafts,
-            indices,
-            statics,
-            duration.as_secs_f32()
-        );
-    } else if site.feed {
-        eprintln!(
-            "generate {} files ({} articles, {} indices, 1 feed, {} static files) in {:.2}s",
-            articles + indices + 1 + statics,
-            articles,
-            indices,
-            statics,
-            duration.as_secs_f32()
-        );
-    } else if site.include_draft {
-        eprintln!(
-            "generate {} files ({} articles, {} drafts, {} indices, {} static files) in {:.2}s",
-            articles + drafts + indices + statics,
-            articles,
-            drafts,
-            indices,
-            statics,
-            duration.as_secs_f32()
-        );
-    } else {
+    let mut counts = vec![(site.articles.len(), "articles")];
+    if site.include_draft {
+        counts.push((site.drafts.len(), "drafts"));
+    }
+    if !site.notes.is_empty() {
+        counts.push((site.notes.len(), "notes"));
+    }
+    if !site.pages.is_empty() {
+        counts.push((site.pages.len(), "pages"));
+    }
+    if !site.series.is_empty() {
+        counts.push((site.series.len(), "series"));
+    }
+    let listings = std::iter::once(&site.index).chain(site.sections.iter().map(|s| &s.index));
+    // index.html and archive.html, and archives of each year and month
+    let indices = listings
+        .map(|index| {
+            2 + index.len()
+                + index
+                    .values()
+                    .map(|articles| site::by_month(articles).len())
+                    .sum::<usize>()
+        })
+        .sum();
+    counts.push((indices, "indices"));
+    if site.feed {
+        counts.push((1 + site.sections.len(), "feeds"));
+    }
+    counts.push((generator::StaticFiles::iter().count(), "static files"));
+    // stderr has nothing but diagnostics in JSON, to be parsed by tools
+    if diagnostics_format == diagnostics::Format::Text {
         eprintln!(
-            "generate {} files ({} articles, {} indices, {} static files) in {:.2}s",
-            articles + indices + statics,
-            articles,
-            indices,
-            statics,
+            "generate {} files ({}) in {:.2}s",
+            counts.iter().map(|(n, _)| n).sum::<usize>(),
+            counts
+                .iter()
+                .map(|(n, what)| format!("{} {}", n, what))
+                .collect::<Vec<_>>()
+                .join(", "),
             duration.as_secs_f32()
         );
     }
 
-    Ok(())
+    site.diagnostics.report(diagnostics_format);
+    if args.strict && !site.diagnostics.is_empty() {
+        return Ok(ExitCode::FAILURE);
+    }
+
+    Ok(ExitCode::SUCCESS)
 }
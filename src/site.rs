@@ -1,19 +1,26 @@
-use chrono::{Datelike, NaiveDateTime};
-use indextree::NodeEdge;
+use chrono::{
+    DateTime, Datelike, LocalResult, NaiveDate, NaiveDateTime, NaiveTime, Offset, TimeDelta,
+    TimeZone, Utc,
+};
+use chrono_tz::Tz;
+use indextree::{NodeEdge, NodeId};
 use orgize::{
     elements::{Element, Timestamp, Title},
-    export::HtmlHandler,
+    export::{HtmlEscape, HtmlHandler},
     Headline, Org,
 };
 use std::borrow::Cow;
-use std::cell::RefCell;
-use std::cmp::Ordering;
-use std::collections::{BTreeMap, BTreeSet};
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fmt;
 use std::io::{Error, Write};
-use std::rc::Rc;
+use std::path::{Component, Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Arc, RwLock};
 use url::Url;
 
-use crate::utils::notice;
+use crate::diagnostics::{Diagnostic, Diagnostics, Kind, Location, Severity};
+use crate::include;
 
 #[derive(Clone, Eq, PartialEq, PartialOrd, Ord)]
 pub struct Id(String);
@@ -22,8 +29,11 @@ impl Id {
     pub fn new(id: String) -> Self {
         Id(id)
     }
-    pub fn to_string(&self) -> String {
-        self.0.clone()
+}
+
+impl fmt::Display for Id {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.fmt(f)
     }
 }
 
@@ -32,16 +42,44 @@ pub struct Article {
     pub published: NaiveDateTime,
     pub updated: Option<NaiveDateTime>,
     pub title: String,
-    pub org: Rc<RefCell<Org<'static>>>,
+    pub org: Arc<RwLock<Org<'static>>>,
     pub headline: Headline,
-    pub subids: Vec<Id>,
+    /// IDs of sub-headlines and where they are written
+    pub subids: Vec<(Id, Location)>,
     pub is_draft: bool,
+    /// where this article is written
+    pub location: Location,
+    /// where links are written (in the whole file)
+    pub link_locations: Arc<HashMap<NodeId, Location>>,
+    /// notes taken in LOGBOOK (newest first)
+    pub changelog: Vec<LogbookEntry>,
+    /// published date is later than `Site::now`
+    pub is_future: bool,
+    /// headings in this article (including itself), targets of `*heading` and `#custom-id` links
+    pub headings: Vec<Heading>,
+    /// tags of the headline following `#+FILETAGS` (except ones required by publish/draft tags)
+    pub tags: Vec<String>,
+    /// `EXPORT_AUTHOR` property or `#+AUTHOR`
+    pub author: Option<String>,
+    /// `EXPORT_LANGUAGE` property or `#+LANGUAGE`
+    pub language: Option<String>,
+    pub options: ExportOptions,
+    /// name of the section this article is listed in (see `Section`)
+    pub section: Option<String>,
+    /// `SERIES` property of the headline or its nearest ancestor
+    pub series: Option<String>,
 }
 
 impl Article {
     pub fn html<E: From<Error>, H: HtmlHandler<E>>(&self, handler: &mut H) -> Result<String, E> {
         let mut buf = Vec::new();
-        write_headline_html(&self.org.borrow(), &self.headline, &mut buf, handler)?;
+        write_headline_html(
+            &self.org.read().unwrap(),
+            &self.headline,
+            &self.options,
+            &mut buf,
+            handler,
+        )?;
 
         Ok(String::from_utf8(buf).unwrap())
     }
@@ -54,23 +92,51 @@ pub fn id_to_path(id: &Id) -> String {
     format!("articles/{}/{}.html", id.0.chars().last().unwrap(), id.0)
 }
 
+/// A headline exported as a standalone page, which is not a dated article.
+///
+/// Pages are tagged "page" (or have `EXPORT_PAGE` property), or exported as notes (see
+/// `UnresolvedLinks::Note`).
+pub struct Page {
+    pub id: Option<Id>,
+    pub title: String,
+    pub path: String,
+    pub org: Arc<RwLock<Org<'static>>>,
+    pub headline: Headline,
+    pub location: Location,
+    /// where links are written (in the whole file)
+    pub link_locations: Arc<HashMap<NodeId, Location>>,
+    pub headings: Vec<Heading>,
+    /// `EXPORT_LANGUAGE` property or `#+LANGUAGE`
+    pub language: Option<String>,
+    pub options: ExportOptions,
+}
+
+impl Page {
+    pub fn html<E: From<Error>, H: HtmlHandler<E>>(&self, handler: &mut H) -> Result<String, E> {
+        let mut buf = Vec::new();
+        write_headline_html(
+            &self.org.read().unwrap(),
+            &self.headline,
+            &self.options,
+            &mut buf,
+            handler,
+        )?;
+
+        Ok(String::from_utf8(buf).unwrap())
+    }
+}
+
 impl PartialEq for Article {
     fn eq(&self, other: &Self) -> bool {
         self.id.eq(&other.id)
     }
 }
 
-impl Eq for Article {
-    fn assert_receiver_is_total_eq(&self) {}
-}
+impl Eq for Article {}
 
 impl PartialOrd for Article {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        if self.published != other.published {
-            self.published.partial_cmp(&other.published)
-        } else {
-            self.id.partial_cmp(&other.id)
-        }
+        Some(self.cmp(other))
     }
 }
 
@@ -84,19 +150,551 @@ impl Ord for Article {
     }
 }
 
+/// A headline in an article, which can be linked by `[[*title]]` or `[[#custom-id]]`.
+#[derive(Debug, Clone)]
+pub struct Heading {
+    pub title: String,
+    pub custom_id: Option<String>,
+    /// `id` attribute of the rendered heading
+    pub anchor: String,
+}
+
+impl Heading {
+    /// Whether this heading is a target of link `path` (`*title` or `#custom-id`).
+    pub fn matches(&self, path: &str) -> bool {
+        if let Some(title) = path.strip_prefix('*') {
+            normalize_space(title) == normalize_space(&self.title)
+        } else if let Some(custom_id) = path.strip_prefix('#') {
+            self.custom_id.as_deref() == Some(custom_id)
+        } else {
+            false
+        }
+    }
+}
+
+/// How `a^b` and `a_b` are rendered (`^:` of `#+OPTIONS`).
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub enum SubSuperscript {
+    /// as is (`^:nil`)
+    #[default]
+    Off,
+    /// only `a^{b}` and `a_{b}` are rendered as superscript and subscript (`^:{}`)
+    Braces,
+    /// `^:t`
+    On,
+}
+
+/// Export options given by `#+OPTIONS` (or `EXPORT_OPTIONS` property).
+///
+/// Unlike org-mode, all of them are off by default.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct ExportOptions {
+    /// render table of contents (up to the depth)
+    pub toc: Option<usize>,
+    /// number sub-headlines (up to the depth)
+    pub num: Option<usize>,
+    pub sub_superscript: SubSuperscript,
+}
+
+impl ExportOptions {
+    /// Apply options like `toc:2 num:nil ^:{}`. Unknown options are ignored.
+    fn apply(&mut self, value: &str) {
+        for option in value.split_whitespace() {
+            let Some((key, value)) = option.split_once(':') else {
+                continue;
+            };
+            let depth = || match value {
+                "nil" => None,
+                "t" => Some(usize::MAX),
+                n => n.parse().ok(),
+            };
+            match key {
+                "toc" => self.toc = depth(),
+                "num" => self.num = depth(),
+                "^" => {
+                    self.sub_superscript = match value {
+                        "t" => SubSuperscript::On,
+                        "{}" => SubSuperscript::Braces,
+                        _ => SubSuperscript::Off,
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Keywords of an org file (`#+KEY: value`), which apply to all articles in it.
+#[derive(Debug, Clone, Default)]
+struct FileKeywords {
+    author: Option<String>,
+    language: Option<String>,
+    filetags: Vec<String>,
+    options: ExportOptions,
+}
+
+impl FileKeywords {
+    fn new(org: &Org) -> Self {
+        let mut keywords = FileKeywords::default();
+        for keyword in org.keywords() {
+            let value = keyword.value.trim();
+            match keyword.key.to_ascii_uppercase().as_str() {
+                "AUTHOR" => keywords.author = Some(value.to_string()),
+                "LANGUAGE" => keywords.language = Some(value.to_string()),
+                "FILETAGS" => keywords.filetags.extend(
+                    value
+                        .split([':', ' '])
+                        .filter(|tag| !tag.is_empty())
+                        .map(|tag| tag.to_string()),
+                ),
+                "OPTIONS" => keywords.options.apply(value),
+                _ => {}
+            }
+        }
+        keywords
+    }
+}
+
 #[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd)]
 pub struct Year(pub i32);
 
+/// Group `articles` by the month they are published (1 to 12), keeping the order.
+pub fn by_month<'a>(
+    articles: impl IntoIterator<Item = &'a Arc<Article>>,
+) -> BTreeMap<u32, Vec<&'a Arc<Article>>> {
+    let mut months = BTreeMap::<_, Vec<_>>::new();
+    for article in articles {
+        months
+            .entry(article.published.month())
+            .or_default()
+            .push(article);
+    }
+    months
+}
+
+/// A part of the site which has its own index, archives and feed under `NAME/`.
+///
+/// Articles are listed in the first section matching their tags, or the one named by their
+/// `SECTION` property. Other articles are listed in the top-level index.
+#[derive(Clone)]
+pub struct Section {
+    pub name: String,
+    pub tags: TagMatch,
+    pub index: BTreeMap<Year, BTreeSet<Arc<Article>>>,
+    pub last_update: Option<NaiveDateTime>,
+}
+
+impl Section {
+    /// URL prefix of pages of this section
+    pub fn prefix(&self) -> String {
+        format!("{}/", self.name)
+    }
+}
+
+impl fmt::Debug for Section {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Section")
+            .field("name", &self.name)
+            .field("tags", &self.tags)
+            .finish_non_exhaustive()
+    }
+}
+
+impl FromStr for Section {
+    type Err = String;
+
+    /// `NAME=MATCH`, or `NAME` for articles tagged `NAME`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, tags) = s.split_once('=').unwrap_or((s, s));
+        let is_valid = |part: &str| {
+            !part.is_empty()
+                && part != ".."
+                && part != "."
+                && part
+                    .chars()
+                    .all(|c| c.is_alphanumeric() || ['-', '_', '.'].contains(&c))
+        };
+        if !name.split('/').all(is_valid) {
+            return Err(format!("invalid section name: {}", name));
+        }
+        // directories written by the generator
+        let top = name.split('/').next().unwrap_or_default();
+        if ["articles", "notes", "series", "static"].contains(&top) {
+            return Err(format!("section name is reserved: {}", name));
+        }
+        Ok(Section {
+            name: name.to_string(),
+            tags: tags.parse()?,
+            index: BTreeMap::new(),
+            last_update: None,
+        })
+    }
+}
+
+/// An entry of the navigation menu in the site header, given as `LABEL=TARGET`.
+///
+/// `TARGET` is an `id:` link, an absolute URL, or a path relative to the site root.
+#[derive(Debug, Clone)]
+pub struct MenuItem {
+    pub label: String,
+    pub target: String,
+}
+
+impl FromStr for MenuItem {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once('=') {
+            Some((label, target)) if !label.is_empty() && !target.is_empty() => Ok(MenuItem {
+                label: label.to_string(),
+                target: target.to_string(),
+            }),
+            _ => Err(format!("menu item must be LABEL=TARGET: {}", s)),
+        }
+    }
+}
+
+/// How to render `id:` links to headlines which are not published.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum UnresolvedLinks {
+    /// plain text
+    Text,
+    /// link to an external URL (`{id}` in the template is replaced with the ID)
+    Url(String),
+    /// `<span class="unpublished">`
+    Span,
+    /// export the headline as a hidden "note" page and link to it
+    Note,
+}
+
+impl FromStr for UnresolvedLinks {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            Some(("url", template)) => Ok(UnresolvedLinks::Url(template.to_string())),
+            _ => match s {
+                "text" => Ok(UnresolvedLinks::Text),
+                "span" => Ok(UnresolvedLinks::Span),
+                "note" => Ok(UnresolvedLinks::Note),
+                _ => Err(format!("unknown mode: {}", s)),
+            },
+        }
+    }
+}
+
+/// Which headlines become articles by their tags (see `Site::publish_tags`).
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum BlogTagMode {
+    /// headlines which have the tag themselves
+    Own,
+    /// children of headlines which have the tag (the tagged one is a container)
+    Children,
+}
+
+impl FromStr for BlogTagMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "own" => Ok(BlogTagMode::Own),
+            "children" => Ok(BlogTagMode::Children),
+            _ => Err(format!("unknown blog tag mode: {}", s)),
+        }
+    }
+}
+
+/// Tag match expression like org agenda, e.g. `blog+team-internal|notes`.
+///
+/// `|` separates alternatives, and each alternative requires tags prefixed with `+` (or
+/// nothing) and forbids tags prefixed with `-`.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct TagMatch(Vec<Vec<(bool, String)>>);
+
+impl TagMatch {
+    /// Whether `tags` match one of alternatives.
+    ///
+    /// At least one required tag of the matched alternative (e.g. `blog` or `team` of
+    /// `blog+team`) must also be in `marker` (tags of the headline itself, or its parent), so
+    /// that sub-headlines don't match only by inherited tags.
+    fn matches(&self, tags: &[String], marker: Option<&[String]>) -> bool {
+        self.0.iter().any(|terms| {
+            terms
+                .iter()
+                .all(|(required, tag)| tags.contains(tag) == *required)
+                && marker.is_none_or(|marker| {
+                    terms
+                        .iter()
+                        .any(|(required, tag)| *required && marker.contains(tag))
+                })
+        })
+    }
+    /// Whether `tag` is required by one of alternatives.
+    fn requires(&self, tag: &str) -> bool {
+        self.0
+            .iter()
+            .flatten()
+            .any(|(required, t)| *required && t == tag)
+    }
+}
+
+impl FromStr for TagMatch {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut alternatives = Vec::new();
+        for alternative in s.split('|') {
+            let mut terms = Vec::new();
+            let mut chars = alternative.trim().chars().peekable();
+            while let Some(c) = chars.next() {
+                let (required, mut tag) = match c {
+                    '+' => (true, String::new()),
+                    '-' => (false, String::new()),
+                    _ => (true, c.to_string()),
+                };
+                while let Some(c) = chars.next_if(|c| !['+', '-'].contains(c)) {
+                    tag.push(c);
+                }
+                if tag.is_empty()
+                    || !tag
+                        .chars()
+                        .all(|c| c.is_alphanumeric() || "_@#%".contains(c))
+                {
+                    return Err(format!("invalid tag match: {}", s));
+                }
+                terms.push((required, tag));
+            }
+            if !terms.iter().any(|(required, _)| *required) {
+                return Err(format!("tag match requires at least one tag: {}", s));
+            }
+            alternatives.push(terms);
+        }
+        Ok(TagMatch(alternatives))
+    }
+}
+
+/// Kind of an entry in LOGBOOK drawer.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum LogbookEntryKind {
+    /// `- Note taken on [...] \\`
+    Note,
+    /// `- State "DONE" from "TODO" [...]`
+    StateChange,
+    /// other timestamps
+    Other,
+}
+
+impl FromStr for LogbookEntryKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "note" => Ok(LogbookEntryKind::Note),
+            "state" => Ok(LogbookEntryKind::StateChange),
+            "other" => Ok(LogbookEntryKind::Other),
+            _ => Err(format!("unknown LOGBOOK entry kind: {}", s)),
+        }
+    }
+}
+
+pub struct LogbookEntry {
+    pub kind: LogbookEntryKind,
+    pub date: NaiveDateTime,
+    /// text after the timestamp (note body)
+    pub text: String,
+}
+
+/// Where to find a publish date of an article.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum DateSource {
+    Scheduled,
+    Closed,
+    Deadline,
+    /// property in the property drawer (e.g. `PUBLISHED`, `EXPORT_DATE`)
+    Property(String),
+}
+
+impl DateSource {
+    fn find(&self, title: &Title) -> Option<NaiveDateTime> {
+        match self {
+            DateSource::Scheduled => title.scheduled().and_then(timestamp_to_datetime),
+            DateSource::Closed => title.closed().and_then(timestamp_to_datetime),
+            DateSource::Deadline => title.deadline().and_then(timestamp_to_datetime),
+            DateSource::Property(name) => get_property(title, name).and_then(parse_datetime),
+        }
+    }
+}
+
+impl FromStr for DateSource {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "" => Err("empty date source".to_string()),
+            "scheduled" => Ok(DateSource::Scheduled),
+            "closed" => Ok(DateSource::Closed),
+            "deadline" => Ok(DateSource::Deadline),
+            _ => Ok(DateSource::Property(s.to_string())),
+        }
+    }
+}
+
+impl fmt::Display for DateSource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DateSource::Scheduled => "SCHEDULED".fmt(f),
+            DateSource::Closed => "CLOSED".fmt(f),
+            DateSource::Deadline => "DEADLINE".fmt(f),
+            DateSource::Property(name) => write!(f, ":{}:", name),
+        }
+    }
+}
+
 pub struct Site {
     pub name: String,
     pub url: Option<Url>,
     pub feed: bool,
     pub include_draft: bool,
-    pub index: BTreeMap<Year, BTreeSet<Rc<Article>>>,
-    pub articles: BTreeMap<Id, Rc<Article>>,
-    pub drafts: BTreeMap<Id, Rc<Article>>,
+    /// render articles published in the future (they are not listed in index, archives and feeds)
+    pub include_future: bool,
+    pub now: DateTime<Utc>,
+    /// articles which are not in any section, by published year
+    pub index: BTreeMap<Year, BTreeSet<Arc<Article>>>,
+    pub articles: BTreeMap<Id, Arc<Article>>,
+    pub drafts: BTreeMap<Id, Arc<Article>>,
     pub last_update: Option<NaiveDateTime>,
+    pub sections: Vec<Section>,
+    /// articles of each series, in the order of `Article`
+    pub series: BTreeMap<String, BTreeSet<Arc<Article>>>,
+    /// navigation menu in the site header
+    pub menu: Vec<MenuItem>,
+    /// show the number of articles of each month on archive pages of years
+    pub calendar: bool,
+    /// max number of related articles shown on each article (0 to disable)
+    pub related: usize,
     pub subid_to_articleid_map: BTreeMap<Id, Id>,
+    pub time_zone: Tz,
+    pub date_sources: Vec<DateSource>,
+    /// kinds of LOGBOOK entries which update an article
+    pub updated_from: Vec<LogbookEntryKind>,
+    /// render notes in LOGBOOK as a changelog
+    pub changelog: bool,
+    pub diagnostics: Diagnostics,
+    /// report duplicated IDs as warnings instead of errors
+    pub allow_duplicate_ids: bool,
+    /// where each ID (of articles and their sub-headlines) is defined,
+    /// including drafts and future articles which are not loaded
+    id_locations: BTreeMap<Id, Location>,
+    /// IDs of drafts and their sub-headlines, including not loaded ones
+    draft_ids: BTreeSet<Id>,
+    /// IDs of future articles and their sub-headlines which are not loaded
+    future_ids: BTreeSet<Id>,
+    pub unresolved_links: UnresolvedLinks,
+    pub blog_tag_mode: BlogTagMode,
+    /// headlines matching this become articles
+    pub publish_tags: TagMatch,
+    /// headlines matching this become drafts
+    pub draft_tags: TagMatch,
+    /// tags which are not inherited by sub-headlines
+    pub tags_exclude_from_inheritance: Vec<String>,
+    /// tags which exclude headlines (and their subtrees) from export
+    pub exclude_tags: Vec<String>,
+    /// all headlines which have ID (except excluded ones)
+    headlines: BTreeMap<Id, IdHeadline>,
+    /// headlines exported as notes (see `UnresolvedLinks::Note`)
+    pub notes: BTreeMap<Id, Arc<Page>>,
+    /// headlines matching this become standalone pages
+    pub page_tags: TagMatch,
+    /// standalone pages by path
+    pub pages: BTreeMap<String, Arc<Page>>,
+    /// paths of pages which have each ID (as their own ID or sub-headline's one)
+    page_ids: BTreeMap<Id, String>,
+    /// all headings of each input file (keyed by normalized path)
+    input_files: BTreeMap<PathBuf, Vec<Heading>>,
+    /// loaded articles (and drafts) of each input file, in document order
+    file_articles: BTreeMap<PathBuf, Vec<Id>>,
+}
+
+/// A headline which has ID, and where it is written.
+#[derive(Clone)]
+struct IdHeadline {
+    org: Arc<RwLock<Org<'static>>>,
+    headline: Headline,
+    location: Location,
+    link_locations: Arc<HashMap<NodeId, Location>>,
+    /// `EXPORT_LANGUAGE` property or `#+LANGUAGE` of the file
+    language: Option<String>,
+    /// `EXPORT_OPTIONS` property or `#+OPTIONS` of the file
+    options: ExportOptions,
+}
+
+/// An org file parsed but not loaded into `Site` yet.
+///
+/// Parsing doesn't need `Site`, so files can be parsed in parallel.
+pub struct OrgFile {
+    file: Option<PathBuf>,
+    /// where headlines are written (they may come from included files)
+    locations: Vec<Location>,
+    /// where links are written
+    link_locations: HashMap<NodeId, Location>,
+    org: Org<'static>,
+    /// problems found while expanding `#+INCLUDE:` and `#+SETUPFILE:`
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl OrgFile {
+    pub fn parse(file: Option<PathBuf>, data: String) -> Self {
+        let expanded = include::expand(file.as_deref(), &data);
+        let locations = headline_lines(&expanded.text)
+            .into_iter()
+            .map(|line| expanded.sources[line - 1].clone())
+            .collect();
+        let org = Org::parse_string(expanded.text.clone());
+        let link_locations = link_offsets(&org, &expanded.text)
+            .into_iter()
+            .map(|(node, offset)| {
+                let line = expanded.text[..offset].matches('\n').count();
+                (node, expanded.sources[line].clone())
+            })
+            .collect();
+        OrgFile {
+            file,
+            locations,
+            link_locations,
+            org,
+            diagnostics: expanded.diagnostics,
+        }
+    }
+}
+
+/// Result of `Site::resolve_id`.
+pub enum Resolved<'a> {
+    /// the article (or its sub-headline, with an anchor) which is generated in this build
+    Article(&'a Arc<Article>, Option<&'a Id>),
+    /// the standalone page (or its sub-headline, with an anchor)
+    Page(&'a Arc<Page>, Option<&'a Id>),
+    Note(&'a Arc<Page>),
+    /// draft which is not generated in this build
+    ExcludedDraft,
+    /// future article which is not generated in this build
+    Embargoed,
+    /// headline exists, but it is not published
+    Unpublished,
+    NotFound,
+}
+
+/// Result of `Site::resolve_file`.
+pub enum ResolvedFile<'a> {
+    /// the article (and the heading if search option is given) which is generated in this build
+    Article(&'a Arc<Article>, Option<&'a Heading>),
+    /// the file has more than one article, but no search option is given
+    Ambiguous(usize),
+    /// the heading (or the file) exists, but it is not published
+    Unpublished,
+    /// the file is an input file, but the heading is not found in it
+    NotFound,
+    /// the file is not an input file
+    NotInput,
 }
 
 impl Site {
@@ -106,32 +704,554 @@ impl Site {
             url,
             feed,
             include_draft,
+            include_future: false,
+            now: Utc::now(),
             index: BTreeMap::new(),
             articles: BTreeMap::new(),
             drafts: BTreeMap::new(),
             last_update: None,
+            sections: Vec::new(),
+            series: BTreeMap::new(),
+            menu: Vec::new(),
+            calendar: false,
+            related: 0,
             subid_to_articleid_map: BTreeMap::new(),
+            time_zone: Tz::UTC,
+            date_sources: vec![DateSource::Scheduled],
+            updated_from: vec![
+                LogbookEntryKind::Note,
+                LogbookEntryKind::StateChange,
+                LogbookEntryKind::Other,
+            ],
+            changelog: false,
+            diagnostics: Diagnostics::new(),
+            allow_duplicate_ids: false,
+            id_locations: BTreeMap::new(),
+            draft_ids: BTreeSet::new(),
+            future_ids: BTreeSet::new(),
+            unresolved_links: UnresolvedLinks::Text,
+            blog_tag_mode: BlogTagMode::Own,
+            publish_tags: "blog".parse().unwrap(),
+            draft_tags: "draft".parse().unwrap(),
+            tags_exclude_from_inheritance: Vec::new(),
+            exclude_tags: vec!["PRIVATE".to_string(), "noexport".to_string()],
+            headlines: BTreeMap::new(),
+            notes: BTreeMap::new(),
+            page_tags: "page".parse().unwrap(),
+            pages: BTreeMap::new(),
+            page_ids: BTreeMap::new(),
+            input_files: BTreeMap::new(),
+            file_articles: BTreeMap::new(),
+        }
+    }
+    /// Interpret `datetime` (written in org file) as a local time of the site's time zone.
+    pub fn localize(&self, datetime: &NaiveDateTime) -> DateTime<Tz> {
+        match self.time_zone.from_local_datetime(datetime) {
+            LocalResult::Single(datetime) => datetime,
+            LocalResult::Ambiguous(earliest, _) => {
+                self.diagnostics.warn(
+                    Kind::InvalidLocalTime,
+                    None,
+                    format!(
+                        "{} is ambiguous in {}, use {}",
+                        datetime, self.time_zone, earliest
+                    ),
+                );
+                earliest
+            }
+            LocalResult::None => {
+                // the time is skipped (e.g. DST gap), shift it by the offset just before the gap
+                let before = *datetime - TimeDelta::hours(1);
+                let offset = match self.time_zone.from_local_datetime(&before).earliest() {
+                    Some(before) => before.offset().fix(),
+                    None => self.time_zone.offset_from_utc_datetime(datetime).fix(),
+                };
+                let shifted = self.time_zone.from_utc_datetime(&(*datetime - offset));
+                self.diagnostics.warn(
+                    Kind::InvalidLocalTime,
+                    None,
+                    format!(
+                        "{} does not exist in {}, use {}",
+                        datetime, self.time_zone, shifted
+                    ),
+                );
+                shifted
+            }
+        }
+    }
+    /// Path of the index page of a series.
+    ///
+    /// Names with the same slug (e.g. "C" and "C++") get unique paths (`c.html`, `c-1.html`, ...)
+    /// in the order of names.
+    pub fn series_path(&self, name: &str) -> String {
+        let mut used = HashSet::new();
+        for other in self.series.keys() {
+            let slug = match slugify(other) {
+                slug if slug.is_empty() => "series".to_string(),
+                slug => slug,
+            };
+            let slug = (0..)
+                .map(|n| match n {
+                    0 => slug.clone(),
+                    n => format!("{}-{}", slug, n),
+                })
+                .find(|slug| !used.contains(slug))
+                .unwrap();
+            if other == name {
+                return format!("series/{}.html", slug);
+            }
+            used.insert(slug);
+        }
+        format!("series/{}.html", slugify(name))
+    }
+    /// Find an article which has `id` (as its own ID or sub-headline's one).
+    pub fn resolve_id<'a>(&'a self, id: &'a Id) -> Resolved<'a> {
+        let (article_id, anchor) = match self.subid_to_articleid_map.get(id) {
+            Some(article_id) => (article_id, Some(id)),
+            None => (id, None),
+        };
+        if let Some(article) = self
+            .articles
+            .get(article_id)
+            .or_else(|| self.drafts.get(article_id))
+        {
+            Resolved::Article(article, anchor)
+        } else if let Some(page) = self.page_ids.get(id).map(|path| &self.pages[path]) {
+            Resolved::Page(page, Some(id).filter(|id| page.id.as_ref() != Some(id)))
+        } else if self.draft_ids.contains(id) {
+            Resolved::ExcludedDraft
+        } else if self.future_ids.contains(id) {
+            Resolved::Embargoed
+        } else if let Some(note) = self.notes.get(id) {
+            Resolved::Note(note)
+        } else if self.headlines.contains_key(id) {
+            Resolved::Unpublished
+        } else {
+            Resolved::NotFound
+        }
+    }
+    /// URL of a menu item from a page at `base`, or `None` if its `id:` target is not published.
+    ///
+    /// `id:` targets are resolved like `id:` links in articles.
+    pub fn menu_href(&self, item: &MenuItem, base: &str) -> Option<String> {
+        let Some(id) = item.target.strip_prefix("id:") else {
+            if Url::parse(&item.target).is_ok() {
+                return Some(item.target.clone());
+            }
+            return Some(format!("{}{}", base, item.target.trim_start_matches('/')));
+        };
+        let id = Id::new(id.to_string());
+        let (path, anchor) = match self.resolve_id(&id) {
+            Resolved::Article(article, anchor) => (article.path(), anchor),
+            Resolved::Page(page, anchor) => (page.path.clone(), anchor),
+            Resolved::Note(note) => (note.path.clone(), None),
+            Resolved::Unpublished | Resolved::NotFound => {
+                return match &self.unresolved_links {
+                    UnresolvedLinks::Url(template) => Some(template.replace("{id}", &id.0)),
+                    _ => None,
+                };
+            }
+            Resolved::ExcludedDraft | Resolved::Embargoed => return None,
+        };
+        Some(match anchor {
+            Some(anchor) => format!("{}{}#{}", base, path, anchor),
+            None => format!("{}{}", base, path),
+        })
+    }
+    /// Whether the `id:` target of a menu item is not published, and rendered as
+    /// `<span class="unpublished">` like `id:` links in articles.
+    pub fn menu_unpublished(&self, item: &MenuItem) -> bool {
+        let Some(id) = item.target.strip_prefix("id:") else {
+            return false;
+        };
+        self.unresolved_links == UnresolvedLinks::Span
+            && matches!(
+                self.resolve_id(&Id::new(id.to_string())),
+                Resolved::Unpublished
+            )
+    }
+    /// Articles (and drafts) linked by `id:` links from each article, except itself.
+    pub fn article_links(&self) -> BTreeMap<Id, BTreeSet<Id>> {
+        self.articles
+            .values()
+            .chain(self.drafts.values())
+            .map(|article| {
+                let links = id_links(&article.org.read().unwrap(), &article.headline)
+                    .iter()
+                    .filter_map(|id| match self.resolve_id(id) {
+                        Resolved::Article(target, _) => Some(target.id.clone()),
+                        _ => None,
+                    })
+                    .filter(|id| *id != article.id)
+                    .collect();
+                (article.id.clone(), links)
+            })
+            .collect()
+    }
+    /// Up to `Site::related` published articles related to `article`.
+    ///
+    /// Articles score a point for each tag shared with `article`, and more if either of them
+    /// links to the other (`links` is `Site::article_links`). Ties are ordered by `Article`
+    /// (newer first), so the result is reproducible.
+    pub fn related_articles(
+        &self,
+        article: &Article,
+        links: &BTreeMap<Id, BTreeSet<Id>>,
+    ) -> Vec<&Arc<Article>> {
+        const LINK_SCORE: usize = 2;
+        let links_from = |from: &Id, to: &Id| links.get(from).is_some_and(|ids| ids.contains(to));
+        let mut scored = self
+            .articles
+            .values()
+            .filter(|other| other.id != article.id && !other.is_future)
+            .map(|other| {
+                let shared_tags = other
+                    .tags
+                    .iter()
+                    .filter(|tag| article.tags.contains(tag))
+                    .count();
+                let linked =
+                    links_from(&article.id, &other.id) || links_from(&other.id, &article.id);
+                (shared_tags + if linked { LINK_SCORE } else { 0 }, other)
+            })
+            .filter(|(score, _)| *score > 0)
+            .collect::<Vec<_>>();
+        scored.sort_by(|(a_score, a), (b_score, b)| b_score.cmp(a_score).then(b.cmp(a)));
+        scored
+            .into_iter()
+            .take(self.related)
+            .map(|(_, other)| other)
+            .collect()
+    }
+    /// Find a heading linked by `*title` or `#custom-id` from all articles (and drafts).
+    pub fn resolve_heading(&self, path: &str) -> Option<(&Arc<Article>, &Heading)> {
+        self.articles
+            .values()
+            .chain(self.drafts.values())
+            .find_map(|article| {
+                article
+                    .headings
+                    .iter()
+                    .find(|heading| heading.matches(path))
+                    .map(|heading| (article, heading))
+            })
+    }
+    /// Find an article linked by `file:` link `path` (e.g. `other.org::*Heading`) written in `from`.
+    pub fn resolve_file(&self, from: Option<&Path>, path: &str) -> ResolvedFile<'_> {
+        let (file, search) = match path.split_once("::") {
+            Some((file, search)) => (file, Some(search)),
+            None => (path, None),
+        };
+        let dir = from.and_then(Path::parent).unwrap_or(Path::new(""));
+        let file = normalize_path(&dir.join(file));
+        let Some(headings) = self.input_files.get(&file) else {
+            return ResolvedFile::NotInput;
+        };
+        let articles = self
+            .file_articles
+            .get(&file)
+            .into_iter()
+            .flatten()
+            .filter_map(|id| self.articles.get(id).or_else(|| self.drafts.get(id)))
+            .collect::<Vec<_>>();
+        match search {
+            Some(search) if search.starts_with(['*', '#']) => {
+                if let Some((article, heading)) = articles.iter().find_map(|article| {
+                    article
+                        .headings
+                        .iter()
+                        .find(|heading| heading.matches(search))
+                        .map(|heading| (*article, heading))
+                }) {
+                    ResolvedFile::Article(article, Some(heading))
+                } else if headings.iter().any(|heading| heading.matches(search)) {
+                    ResolvedFile::Unpublished
+                } else {
+                    ResolvedFile::NotFound
+                }
+            }
+            // other search options (e.g. line number) are not supported, link to the file
+            _ => match articles.len() {
+                0 => ResolvedFile::Unpublished,
+                1 => ResolvedFile::Article(articles[0], None),
+                n => ResolvedFile::Ambiguous(n),
+            },
+        }
+    }
+    /// Export headlines linked from articles (and other notes, pages and the menu) but not
+    /// published as notes.
+    ///
+    /// Call this after all org files are loaded.
+    pub fn load_notes(&mut self) {
+        if self.unresolved_links != UnresolvedLinks::Note {
+            return;
+        }
+        let mut queue = self
+            .articles
+            .values()
+            .chain(self.drafts.values())
+            .flat_map(|article| id_links(&article.org.read().unwrap(), &article.headline))
+            .chain(
+                self.pages
+                    .values()
+                    .flat_map(|page| id_links(&page.org.read().unwrap(), &page.headline)),
+            )
+            .chain(
+                self.menu
+                    .iter()
+                    .filter_map(|item| item.target.strip_prefix("id:"))
+                    .map(|id| Id::new(id.to_string())),
+            )
+            .collect::<Vec<_>>();
+        while let Some(id) = queue.pop() {
+            if !matches!(self.resolve_id(&id), Resolved::Unpublished) {
+                continue;
+            }
+            let IdHeadline {
+                org,
+                headline,
+                location,
+                link_locations,
+                language,
+                options,
+            } = self.headlines[&id].clone();
+            queue.extend(id_links(&org.read().unwrap(), &headline));
+            let title = headline.title(&org.read().unwrap()).raw.to_string();
+            let headings = collect_headings(&headline, &org.read().unwrap());
+            let note = Page {
+                id: Some(id.clone()),
+                headings,
+                path: format!("notes/{}/{}.html", id.0.chars().last().unwrap(), id.0),
+                title,
+                org,
+                headline,
+                location,
+                link_locations,
+                language,
+                options,
+            };
+            self.notes.insert(id, Arc::new(note));
         }
     }
-    pub fn load_org_data(&mut self, data: String) {
-        let org = Rc::new(RefCell::new(Org::parse_string(data)));
+    /// Record where `id` is defined. Returns `false` (and report it) if it is already used
+    /// by another headline.
+    fn register_id(&mut self, id: &Id, location: &Location, what: &str) -> bool {
+        if let Some(first) = self.id_locations.get(id) {
+            if first == location {
+                // e.g. an article nested in another article
+                return true;
+            }
+            self.diagnostics.push(Diagnostic {
+                severity: if self.allow_duplicate_ids {
+                    Severity::Warning
+                } else {
+                    Severity::Error
+                },
+                kind: Kind::DuplicateId,
+                location: Some(location.clone()),
+                message: format!("{}ID \"{}\" is already used at {}", what, id, first),
+            });
+            false
+        } else {
+            self.id_locations.insert(id.clone(), location.clone());
+            true
+        }
+    }
+    /// What the generator writes at `path` other than pages, if any.
+    fn generated_path(&self, path: &str) -> Option<&'static str> {
+        for (dir, what) in [
+            ("articles/", "articles"),
+            ("notes/", "notes"),
+            ("series/", "series"),
+            ("static/", "static files"),
+        ] {
+            if path.starts_with(dir) {
+                return Some(what);
+            }
+        }
+        if self
+            .sections
+            .iter()
+            .any(|section| path.starts_with(&section.prefix()))
+        {
+            return Some("a section");
+        }
+        let is_number = |s: &str| !s.is_empty() && s.chars().all(|c| c.is_ascii_digit());
+        match path.strip_suffix(".html") {
+            Some("index") => Some("the index"),
+            Some("archive") => Some("the archives"),
+            Some(name)
+                if is_number(name)
+                    || name
+                        .split_once('/')
+                        .is_some_and(|(year, month)| is_number(year) && is_number(month)) =>
+            {
+                Some("an archive")
+            }
+            _ if path == "atom.xml" => Some("the feed"),
+            _ => None,
+        }
+    }
+    fn add_page(&mut self, page: Page, subids: Vec<(Id, Location)>) {
+        if let Some(what) = self.generated_path(&page.path) {
+            self.diagnostics.warn(
+                Kind::InvalidProperty,
+                Some(page.location.clone()),
+                format!("page \"{}\" would overwrite {}", page.path, what),
+            );
+            return;
+        }
+        if let Some(other) = self.pages.get(&page.path) {
+            self.diagnostics.warn(
+                Kind::InvalidProperty,
+                Some(page.location.clone()),
+                format!(
+                    "page \"{}\" is already exported at {}",
+                    page.path, other.location
+                ),
+            );
+            return;
+        }
+        if let Some(id) = &page.id {
+            if !self.register_id(id, &page.location, "") {
+                return;
+            }
+        }
+        for (subid, location) in subids {
+            if self.register_id(&subid, &location, "sub-headline ") {
+                self.page_ids.insert(subid, page.path.clone());
+            }
+        }
+        if let Some(id) = &page.id {
+            self.page_ids.insert(id.clone(), page.path.clone());
+        }
+        self.pages.insert(page.path.clone(), Arc::new(page));
+    }
+    /// Parse and load org data at once.
+    #[cfg(test)]
+    pub fn load_org_data(&mut self, file: Option<PathBuf>, data: String) {
+        self.load_org_file(OrgFile::parse(file, data));
+    }
+    /// Load articles from a parsed org file.
+    // `Article` has `RwLock` but it doesn't affect ordering
+    #[allow(clippy::mutable_key_type)]
+    pub fn load_org_file(&mut self, org_file: OrgFile) {
+        let OrgFile {
+            file,
+            locations,
+            link_locations,
+            org,
+            diagnostics,
+        } = org_file;
+        let link_locations = Arc::new(link_locations);
+        for diagnostic in diagnostics {
+            self.diagnostics.push(diagnostic);
+        }
+        let org = Arc::new(RwLock::new(org));
 
-        let headlines = org.borrow().headlines().collect::<Vec<_>>();
+        let headlines = org.read().unwrap().headlines().collect::<Vec<_>>();
+        let keywords = FileKeywords::new(&org.read().unwrap());
+        let normalized_file = file.as_deref().map(normalize_path);
+        if let Some(file) = &normalized_file {
+            let org_ = org.read().unwrap();
+            self.input_files.insert(
+                file.clone(),
+                headlines
+                    .iter()
+                    .map(|h| to_heading(h.title(&org_)))
+                    .collect(),
+            );
+        }
+        let locations = headlines
+            .iter()
+            .enumerate()
+            .map(|(i, headline)| {
+                let location = locations.get(i).cloned().unwrap_or(Location {
+                    file: file.clone(),
+                    line: 0,
+                });
+                (headline.headline_node(), location)
+            })
+            .collect::<HashMap<_, _>>();
+        // computed before articles detach their sub-headlines
+        let tags = {
+            let org_ = org.read().unwrap();
+            headlines
+                .iter()
+                .map(|headline| {
+                    let tags = Tags::new(
+                        &org_,
+                        headline,
+                        &keywords.filetags,
+                        &self.tags_exclude_from_inheritance,
+                    );
+                    (headline.headline_node(), tags)
+                })
+                .collect::<HashMap<_, _>>()
+        };
+        for headline in &headlines {
+            let org_ = org.read().unwrap();
+            if let Some(id) = get_id(headline.title(&org_)).filter(|id| !id.0.is_empty()) {
+                if !tags[&headline.headline_node()].is_excluded(&self.exclude_tags) {
+                    let location = locations[&headline.headline_node()].clone();
+                    let title = headline.title(&org_);
+                    self.headlines.entry(id).or_insert(IdHeadline {
+                        org: org.clone(),
+                        headline: *headline,
+                        location,
+                        link_locations: link_locations.clone(),
+                        language: export_language(title, &keywords),
+                        options: export_options(title, &keywords),
+                    });
+                }
+            }
+        }
         for headline in headlines {
-            if let Some(article) = load_article(org.clone(), headline) {
-                let article = Rc::new(article);
+            if let Some((page, subids)) = load_page(
+                self,
+                org.clone(),
+                headline,
+                &locations,
+                &link_locations,
+                &tags,
+                &keywords,
+            ) {
+                self.add_page(page, subids);
+                continue;
+            }
+            if let Some(article) = load_article(
+                self,
+                org.clone(),
+                headline,
+                &locations,
+                &link_locations,
+                &tags,
+                &keywords,
+            ) {
+                let article = Arc::new(article);
 
-                if article.is_draft && !self.include_draft {
+                // sub-headline IDs of a skipped article must not be registered
+                if !self.register_id(&article.id, &article.location, "") {
                     continue;
                 }
+                let subids = article
+                    .subids
+                    .iter()
+                    .filter(|(subid, location)| self.register_id(subid, location, "sub-headline "))
+                    .map(|(subid, _)| subid.clone())
+                    .collect::<Vec<_>>();
+                if article.is_draft {
+                    self.draft_ids.insert(article.id.clone());
+                    self.draft_ids.extend(subids.iter().cloned());
+                }
 
-                let updated = article.updated.unwrap_or(article.published);
-                if let Some(last_update) = self.last_update {
-                    if updated > last_update {
-                        self.last_update = Some(updated);
-                    }
-                } else {
-                    self.last_update = Some(updated);
+                if article.is_draft && !self.include_draft {
+                    continue;
+                }
+                if article.is_future && !self.include_future {
+                    self.future_ids.insert(article.id.clone());
+                    self.future_ids.extend(subids);
+                    continue;
                 }
 
                 if article.is_draft {
@@ -140,100 +1260,356 @@ impl Site {
                     self.articles.insert(article.id.clone(), article.clone());
                 }
 
-                for subid in &article.subids {
+                if let Some(file) = &normalized_file {
+                    self.file_articles
+                        .entry(file.clone())
+                        .or_default()
+                        .push(article.id.clone());
+                }
+
+                for subid in subids {
                     self.subid_to_articleid_map
-                        .insert(subid.clone(), article.id.clone());
+                        .insert(subid, article.id.clone());
+                }
+
+                if article.is_future {
+                    // embargoed, only its page is generated
+                    continue;
+                }
+
+                if let Some(name) = &article.series {
+                    self.series
+                        .entry(name.clone())
+                        .or_default()
+                        .insert(article.clone());
+                }
+
+                let (index, last_update) = match self
+                    .sections
+                    .iter_mut()
+                    .find(|section| Some(&section.name) == article.section.as_ref())
+                {
+                    Some(section) => (&mut section.index, &mut section.last_update),
+                    None => (&mut self.index, &mut self.last_update),
+                };
+
+                let updated = article.updated.unwrap_or(article.published);
+                if let Some(last) = *last_update {
+                    if updated > last {
+                        *last_update = Some(updated);
+                    }
+                } else {
+                    *last_update = Some(updated);
                 }
 
                 let year = Year(article.published.year());
-                if let Some(set) = self.index.get_mut(&year) {
+                if let Some(set) = index.get_mut(&year) {
                     set.insert(article);
                 } else {
                     let mut set = BTreeSet::new();
                     set.insert(article);
-                    self.index.insert(year, set);
+                    index.insert(year, set);
                 }
             }
         }
     }
 }
 
-fn load_article(org: Rc<RefCell<Org<'static>>>, headline: Headline) -> Option<Article> {
-    let mut org_ = org.borrow_mut();
+/// Tags of a headline.
+struct Tags {
+    own: Vec<String>,
+    /// `#+FILETAGS`, tags inherited from ancestors, and own tags
+    inherited: Vec<String>,
+    /// own tags of the parent headline
+    parent: Vec<String>,
+    /// the headline or one of its ancestors is a `COMMENT` headline
+    commented: bool,
+}
+
+impl Tags {
+    fn new(org: &Org, headline: &Headline, filetags: &[String], exclude: &[String]) -> Self {
+        let tags_of = |h: Headline| {
+            h.title(org)
+                .tags
+                .iter()
+                .map(|tag| tag.to_string())
+                .collect::<Vec<_>>()
+        };
+        let own = tags_of(*headline);
+        let ancestors =
+            std::iter::successors(headline.parent(org), |h| h.parent(org)).collect::<Vec<_>>();
+        let mut inherited = Vec::new();
+        let inheritable = filetags
+            .iter()
+            .cloned()
+            .chain(ancestors.iter().rev().flat_map(|h| tags_of(*h)))
+            .filter(|tag| !exclude.contains(tag));
+        for tag in inheritable.chain(own.iter().cloned()) {
+            if !inherited.contains(&tag) {
+                inherited.push(tag);
+            }
+        }
+        let parent = ancestors.first().map(|h| tags_of(*h)).unwrap_or_default();
+        let commented = std::iter::once(headline)
+            .chain(&ancestors)
+            .any(|h| h.title(org).is_commented());
+        Tags {
+            own,
+            inherited,
+            parent,
+            commented,
+        }
+    }
+    /// Whether the headline is excluded from export, with its subtree.
+    fn is_excluded(&self, exclude_tags: &[String]) -> bool {
+        self.commented || self.inherited.iter().any(|tag| exclude_tags.contains(tag))
+    }
+}
+
+/// Load `headline` as a standalone page if it is tagged "page" or has `EXPORT_PAGE` property.
+///
+/// Returns the page and IDs of its sub-headlines.
+fn load_page(
+    site: &Site,
+    org: Arc<RwLock<Org<'static>>>,
+    headline: Headline,
+    locations: &HashMap<NodeId, Location>,
+    link_locations: &Arc<HashMap<NodeId, Location>>,
+    headline_tags: &HashMap<NodeId, Tags>,
+    keywords: &FileKeywords,
+) -> Option<(Page, Vec<(Id, Location)>)> {
+    let location = locations[&headline.headline_node()].clone();
+    let mut org_ = org.write().unwrap();
     let title = headline.title(&org_);
-    let is_draft = title.tags.contains(&Cow::Borrowed("draft"));
-    if !(title.tags.contains(&Cow::Borrowed("blog")) || is_draft) {
+    let tags = &headline_tags[&headline.headline_node()];
+    let export_page = get_property(title, "EXPORT_PAGE");
+    let is_page = export_page.is_some() || site.page_tags.matches(&tags.inherited, Some(&tags.own));
+    if !is_page || tags.is_excluded(&site.exclude_tags) {
         return None;
     }
-    let published = match title.planning.as_ref()?.scheduled.as_ref()? {
-        Timestamp::Active {
-            start,
-            repeater: None,
-            delay: None,
-        } => Some(start.into()),
-        Timestamp::Inactive {
-            start,
-            repeater: None,
-            delay: None,
-        } => Some(start.into()),
-        _ => {
-            notice(&format!(
-                "headline \"{}\" has blog tag, but not SCHEDULED",
-                title.raw
-            ));
-            None
+    let id = get_id(title).filter(|id| !id.0.is_empty());
+    let path = match export_page {
+        Some(path) => match page_path(path) {
+            Some(path) => path,
+            None => {
+                site.diagnostics.warn(
+                    Kind::InvalidProperty,
+                    Some(location),
+                    format!(
+                        "headline \"{}\" has invalid EXPORT_PAGE: {}",
+                        title.raw, path
+                    ),
+                );
+                return None;
+            }
+        },
+        None => {
+            let slug = slugify(&title.raw);
+            if slug.is_empty() {
+                site.diagnostics.warn(
+                    Kind::InvalidProperty,
+                    Some(location),
+                    format!(
+                        "page path can't be made from headline \"{}\", set EXPORT_PAGE",
+                        title.raw
+                    ),
+                );
+                return None;
+            }
+            format!("{}.html", slug)
+        }
+    };
+    let language = export_language(title, keywords);
+    let options = export_options(title, keywords);
+    let title = title.raw.to_string();
+    let subids = collect_ids(&headline, &org_)
+        .into_iter()
+        .map(|(id, subheadline)| (id, locations[&subheadline.headline_node()].clone()))
+        .collect();
+
+    // pages are not dated, LOGBOOK is not needed
+    if let Some(sec_node) = headline.section_node() {
+        let children = sec_node.children(org_.arena()).collect::<Vec<_>>();
+        for child in children {
+            if matches!(&org_[child], Element::Drawer(drawer) if drawer.name == "LOGBOOK") {
+                child.detach(org_.arena_mut());
+            }
+        }
+    }
+    for subheadline in headlines(&headline, &org_) {
+        if headline_tags[&subheadline.headline_node()].is_excluded(&site.exclude_tags) {
+            subheadline.detach(&mut org_);
         }
-    }?;
-    let id = get_id(&title).or_else(|| {
-        notice(&format!(
-            "headline \"{}\" has blog tag, but does not have ID",
-            title.raw
-        ));
+    }
+    let headings = collect_headings(&headline, &org_);
+
+    drop(org_);
+
+    Some((
+        Page {
+            id,
+            title,
+            path,
+            org,
+            headline,
+            location,
+            link_locations: link_locations.clone(),
+            headings,
+            language,
+            options,
+        },
+        subids,
+    ))
+}
+
+/// Path of a page given by `EXPORT_PAGE` (e.g. "about" or "projects/index.html").
+fn page_path(value: &str) -> Option<String> {
+    let path = value.trim().trim_start_matches('/');
+    if path.is_empty()
+        || path
+            .split('/')
+            .any(|part| part.is_empty() || part == "." || part == "..")
+    {
+        return None;
+    }
+    if Path::new(path).extension().is_some() {
+        Some(path.to_string())
+    } else {
+        Some(format!("{}.html", path))
+    }
+}
+
+fn load_article(
+    site: &Site,
+    org: Arc<RwLock<Org<'static>>>,
+    headline: Headline,
+    locations: &HashMap<NodeId, Location>,
+    link_locations: &Arc<HashMap<NodeId, Location>>,
+    headline_tags: &HashMap<NodeId, Tags>,
+    keywords: &FileKeywords,
+) -> Option<Article> {
+    let location = locations[&headline.headline_node()].clone();
+    let date_sources = &site.date_sources;
+    let mut org_ = org.write().unwrap();
+    let title = headline.title(&org_);
+    let Tags {
+        own,
+        inherited,
+        parent,
+        ..
+    } = &headline_tags[&headline.headline_node()];
+    let marker = match site.blog_tag_mode {
+        BlogTagMode::Own => own,
+        BlogTagMode::Children => parent,
+    };
+    // marker tags count even if they are not inherited
+    let match_tags = inherited.iter().chain(marker).cloned().collect::<Vec<_>>();
+    let is_article = site.publish_tags.matches(&match_tags, Some(marker))
+        || site.draft_tags.matches(&match_tags, Some(marker));
+    if !is_article || headline_tags[&headline.headline_node()].is_excluded(&site.exclude_tags) {
+        return None;
+    }
+    let is_draft = site.draft_tags.matches(&match_tags, None);
+    let published = date_sources
+        .iter()
+        .find_map(|source| source.find(title))
+        .or_else(|| {
+            site.diagnostics.warn(
+                Kind::MissingPublishDate,
+                Some(location.clone()),
+                format!(
+                    "headline \"{}\" has blog tag, but no publish date ({})",
+                    title.raw,
+                    date_sources
+                        .iter()
+                        .map(|source| source.to_string())
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ),
+            );
+            None
+        })?;
+    let id = get_id(title).or_else(|| {
+        site.diagnostics.warn(
+            Kind::MissingId,
+            Some(location.clone()),
+            format!(
+                "headline \"{}\" has blog tag, but does not have ID",
+                title.raw
+            ),
+        );
         None
     })?;
     if id.0.is_empty() {
-        notice(&format!(
-            "headline \"{}\" has blog tag, but ID is empty",
-            title.raw
-        ));
+        site.diagnostics.warn(
+            Kind::MissingId,
+            Some(location.clone()),
+            format!("headline \"{}\" has blog tag, but ID is empty", title.raw),
+        );
         return None;
     }
+    let is_future = site.localize(&published) > site.now;
+    let tags = inherited
+        .iter()
+        .filter(|tag| !site.publish_tags.requires(tag) && !site.draft_tags.requires(tag))
+        .cloned()
+        .collect();
+    let author = get_property(title, "EXPORT_AUTHOR")
+        .map(|author| author.to_string())
+        .or_else(|| keywords.author.clone());
+    let language = export_language(title, keywords);
+    let options = export_options(title, keywords);
+    // a parent headline can be a container of a series
+    let series = std::iter::successors(Some(headline), |h| h.parent(&org_))
+        .find_map(|h| get_property(h.title(&org_), "SERIES"))
+        .filter(|name| !name.is_empty())
+        .map(|name| name.to_string());
+    let section = match get_property(title, "SECTION") {
+        Some(name) if site.sections.iter().any(|section| section.name == name) => {
+            Some(name.to_string())
+        }
+        Some(name) => {
+            site.diagnostics.warn(
+                Kind::InvalidProperty,
+                Some(location.clone()),
+                format!("headline \"{}\" has unknown SECTION: {}", title.raw, name),
+            );
+            None
+        }
+        None => site
+            .sections
+            .iter()
+            .find(|section| section.tags.matches(&match_tags, None))
+            .map(|section| section.name.clone()),
+    };
     let title = title.raw.to_string();
-    let subids = collect_ids(&headline, &org_);
+    let subids = collect_ids(&headline, &org_)
+        .into_iter()
+        .map(|(id, subheadline)| (id, locations[&subheadline.headline_node()].clone()))
+        .collect();
 
     let mut updated = None;
+    let mut changelog = Vec::new();
 
     if let Some(sec_node) = headline.section_node() {
-        let children = sec_node.children(&org_.arena()).collect::<Vec<_>>();
+        let children = sec_node.children(org_.arena()).collect::<Vec<_>>();
         for child in children {
             if let Element::Drawer(drawer) = &org_[child] {
                 if drawer.name == "LOGBOOK" {
-                    for c in child.descendants(&org_.arena()) {
-                        // if LOGBOOK has timestamp, record it as an "updated"
-                        if let Element::Timestamp(timestamp) = &org_[c] {
-                            if let Timestamp::Active {
-                                start,
-                                repeater: None,
-                                delay: None,
-                            }
-                            | Timestamp::Inactive {
-                                start,
-                                repeater: None,
-                                delay: None,
-                            } = timestamp
-                            {
-                                let start = start.into();
-                                if start > published {
-                                    if let Some(u) = updated {
-                                        if start > u {
-                                            updated = Some(start);
-                                        }
-                                    } else {
-                                        updated = Some(start);
-                                    }
+                    for entry in logbook_entries(&org_, child) {
+                        // if LOGBOOK has entry, record it as an "updated"
+                        if entry.date > published && site.updated_from.contains(&entry.kind) {
+                            if let Some(u) = updated {
+                                if entry.date > u {
+                                    updated = Some(entry.date);
                                 }
+                            } else {
+                                updated = Some(entry.date);
                             }
                         }
+                        if entry.kind == LogbookEntryKind::Note {
+                            changelog.push(entry);
+                        }
                     }
                     // remove LOGBOOK drawer from section node
                     child.detach(org_.arena_mut());
@@ -241,33 +1617,216 @@ fn load_article(org: Rc<RefCell<Org<'static>>>, headline: Headline) -> Option<Ar
             }
         }
     }
+    // newest first
+    changelog.sort_by_key(|entry| Reverse(entry.date));
 
-    // detach (remove) headline node which have "PRIVATE" tag
+    if let Some(value) = get_property(headline.title(&org_), "UPDATED") {
+        if let Some(date) = parse_datetime(value) {
+            updated = Some(date);
+        } else {
+            site.diagnostics.warn(
+                Kind::InvalidProperty,
+                Some(location.clone()),
+                format!(
+                    "headline \"{}\" has invalid UPDATED property: {}",
+                    title, value
+                ),
+            );
+        }
+    }
+
+    // detach (remove) excluded sub-headlines (and their sub-headlines)
     for subheadline in headlines(&headline, &org_) {
-        let title = subheadline.title(&org_);
-        if title.tags.contains(&Cow::Borrowed("PRIVATE")) {
+        if headline_tags[&subheadline.headline_node()].is_excluded(&site.exclude_tags) {
             subheadline.detach(&mut org_);
         }
     }
 
+    let headings = collect_headings(&headline, &org_);
+
     drop(org_);
 
     Some(Article {
-        id: id,
-        published: published,
-        updated: updated,
-        title: title,
-        org: org,
-        headline: headline,
-        subids: subids,
-        is_draft: is_draft,
+        id,
+        published,
+        updated,
+        title,
+        org,
+        headline,
+        subids,
+        is_draft,
+        location,
+        link_locations: link_locations.clone(),
+        changelog,
+        is_future,
+        headings,
+        tags,
+        author,
+        language,
+        options,
+        section,
+        series,
     })
 }
 
-fn collect_ids(headline: &Headline, org: &Org) -> Vec<Id> {
+/// `EXPORT_LANGUAGE` property of `title`, or `#+LANGUAGE` of the file.
+fn export_language(title: &Title, keywords: &FileKeywords) -> Option<String> {
+    get_property(title, "EXPORT_LANGUAGE")
+        .map(|language| language.to_string())
+        .or_else(|| keywords.language.clone())
+}
+
+/// `#+OPTIONS` of the file overridden by `EXPORT_OPTIONS` property of `title`.
+fn export_options(title: &Title, keywords: &FileKeywords) -> ExportOptions {
+    let mut options = keywords.options.clone();
+    if let Some(value) = get_property(title, "EXPORT_OPTIONS") {
+        options.apply(value);
+    }
+    options
+}
+
+/// Byte offsets in `data` where links in headlines of `org` are written.
+///
+/// orgize doesn't keep positions, so find `[[PATH` of each link in document order.
+fn link_offsets(org: &Org, data: &str) -> Vec<(NodeId, usize)> {
+    let mut offsets = Vec::new();
+    let mut start = 0;
+    for headline in org.headlines() {
+        // sub-headlines are visited later
+        let nodes = [Some(headline.title_node()), headline.section_node()];
+        for node in nodes.into_iter().flatten() {
+            for node in node.descendants(org.arena()) {
+                let Element::Link(link) = &org[node] else {
+                    continue;
+                };
+                if let Some(i) = data[start..].find(&format!("[[{}", link.path)) {
+                    offsets.push((node, start + i));
+                    start += i + 2;
+                }
+            }
+        }
+    }
+    offsets
+}
+
+/// Line numbers (1-origin) of headlines in `data`, in document order.
+fn headline_lines(data: &str) -> Vec<usize> {
+    let mut lines = Vec::new();
+    for (i, line) in data.lines().enumerate() {
+        let rest = line.trim_start_matches('*');
+        if rest.len() < line.len() && (rest.is_empty() || rest.starts_with([' ', '\t'])) {
+            lines.push(i + 1);
+        }
+    }
+    lines
+}
+
+fn logbook_entries(org: &Org, drawer: NodeId) -> Vec<LogbookEntry> {
+    let mut entries = Vec::new();
+    for node in drawer.descendants(org.arena()) {
+        let date = match &org[node] {
+            Element::Timestamp(timestamp) => timestamp_to_datetime(timestamp),
+            _ => None,
+        };
+        let Some(date) = date else {
+            continue;
+        };
+        // text around the timestamp in the same list item
+        let mut before = String::new();
+        let mut after = String::new();
+        if let Some(item) = node
+            .ancestors(org.arena())
+            .find(|n| matches!(org[*n], Element::ListItem(_)))
+        {
+            let mut buf = &mut before;
+            for n in item.descendants(org.arena()) {
+                if n == node {
+                    buf = &mut after;
+                }
+                if let Element::Text { value }
+                | Element::Code { value }
+                | Element::Verbatim { value } = &org[n]
+                {
+                    buf.push_str(value);
+                }
+            }
+        }
+        let kind = if before.starts_with("Note taken on") {
+            LogbookEntryKind::Note
+        } else if before.starts_with("State ") {
+            LogbookEntryKind::StateChange
+        } else {
+            LogbookEntryKind::Other
+        };
+        let text = after
+            .trim_start()
+            .trim_start_matches('\\')
+            .split_whitespace()
+            .collect::<Vec<_>>()
+            .join(" ");
+        entries.push(LogbookEntry { kind, date, text });
+    }
+    entries
+}
+
+fn collect_ids(headline: &Headline, org: &Org) -> Vec<(Id, Headline)> {
     headlines(headline, org)
-        .iter()
-        .filter_map(|child| get_id(&child.title(org)))
+        .into_iter()
+        .filter_map(|child| get_id(child.title(org)).map(|id| (id, child)))
+        .collect()
+}
+
+/// IDs linked by `id:` links in the headline (and its sub-headlines).
+fn id_links(org: &Org, headline: &Headline) -> Vec<Id> {
+    headline
+        .headline_node()
+        .descendants(org.arena())
+        .filter_map(|node| match &org[node] {
+            Element::Link(link) => link
+                .path
+                .strip_prefix("id:")
+                .map(|id| Id::new(id.to_string())),
+            _ => None,
+        })
+        .collect()
+}
+
+fn collect_headings(headline: &Headline, org: &Org) -> Vec<Heading> {
+    heading_anchors(headline, org)
+        .into_iter()
+        .map(|(h, anchor)| Heading {
+            anchor,
+            ..to_heading(h.title(org))
+        })
+        .collect()
+}
+
+fn to_heading(title: &Title) -> Heading {
+    Heading {
+        title: title.raw.to_string(),
+        custom_id: get_property(title, "CUSTOM_ID").map(|id| id.to_string()),
+        anchor: heading_anchor(title),
+    }
+}
+
+/// `headline` and its sub-headlines with their anchors, which are unique in the article
+/// (the second "Intro" heading becomes "intro-1").
+fn heading_anchors(headline: &Headline, org: &Org) -> Vec<(Headline, String)> {
+    let mut used = HashSet::new();
+    std::iter::once(*headline)
+        .chain(headlines(headline, org))
+        .map(|h| {
+            let anchor = heading_anchor(h.title(org));
+            let anchor = (0..)
+                .map(|n| match n {
+                    0 => anchor.clone(),
+                    n => format!("{}-{}", anchor, n),
+                })
+                .find(|anchor| !used.contains(anchor))
+                .unwrap();
+            used.insert(anchor.clone());
+            (h, anchor)
+        })
         .collect()
 }
 
@@ -281,18 +1840,173 @@ fn headlines(headline: &Headline, org: &Org) -> Vec<Headline> {
 }
 
 pub fn get_id(title: &Title) -> Option<Id> {
+    get_property(title, "ID").map(|value| Id::new(value.to_string()))
+}
+
+/// `id` attribute of a heading: its ID, CUSTOM_ID or a slug made from its title.
+fn heading_anchor(title: &Title) -> String {
+    if let Some(id) = get_id(title) {
+        id.0
+    } else if let Some(custom_id) = get_property(title, "CUSTOM_ID") {
+        custom_id.to_string()
+    } else {
+        slugify(&title.raw)
+    }
+}
+
+fn slugify(s: &str) -> String {
+    s.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(|word| word.to_lowercase())
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+/// Canonicalize `path` if it exists, or resolve `.` and `..` lexically.
+fn normalize_path(path: &Path) -> PathBuf {
+    if let Ok(path) = path.canonicalize() {
+        return path;
+    }
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir if normalized.file_name().is_some() => {
+                normalized.pop();
+            }
+            _ => normalized.push(component),
+        }
+    }
+    normalized
+}
+
+fn normalize_space(s: &str) -> String {
+    s.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+fn get_property<'a>(title: &'a Title, name: &str) -> Option<&'a str> {
     title.properties.iter().find_map(|(key, value)| {
-        if key == "ID" {
-            Some(Id::new(value.to_string()))
+        if key.eq_ignore_ascii_case(name) {
+            Some(value.as_ref())
         } else {
             None
         }
     })
 }
 
+fn timestamp_to_datetime(timestamp: &Timestamp) -> Option<NaiveDateTime> {
+    match timestamp {
+        Timestamp::Active {
+            start,
+            repeater: None,
+            delay: None,
+        }
+        | Timestamp::Inactive {
+            start,
+            repeater: None,
+            delay: None,
+        } => Some(start.into()),
+        _ => None,
+    }
+}
+
+/// Parse a timestamp written as a property value, like `<2025-01-01 Wed 12:00>`,
+/// `[2025-01-01 Wed]` or `2025-01-01`.
+fn parse_datetime(value: &str) -> Option<NaiveDateTime> {
+    let value = value
+        .trim()
+        .trim_start_matches(['<', '['])
+        .trim_end_matches(['>', ']']);
+    let mut words = value.split_whitespace();
+    let date = NaiveDate::parse_from_str(words.next()?, "%Y-%m-%d").ok()?;
+    let time = words
+        .find_map(|word| NaiveTime::parse_from_str(word, "%H:%M").ok())
+        .unwrap_or(NaiveTime::MIN);
+    Some(date.and_time(time))
+}
+
+/// Remove "," escaping lines in blocks which start with "*" or "#+".
+fn unescape_block_line(line: &str) -> Cow<'_, str> {
+    let rest = line.trim_start();
+    match rest.strip_prefix(',') {
+        Some(escaped) if escaped.starts_with('*') || escaped.starts_with("#+") => {
+            Cow::Owned(format!("{}{}", &line[..line.len() - rest.len()], escaped))
+        }
+        _ => Cow::Borrowed(line),
+    }
+}
+
+/// Sub-headlines with their levels relative to `headline` and section numbers (like "1.2").
+fn sections(org: &Org, headline: &Headline) -> Vec<(Headline, usize, String)> {
+    let mut counters: Vec<usize> = Vec::new();
+    headlines(headline, org)
+        .into_iter()
+        .map(|h| {
+            let level = h.level() - headline.level();
+            counters.resize(level, 0);
+            counters[level - 1] += 1;
+            let number = counters
+                .iter()
+                .map(|n| n.to_string())
+                .collect::<Vec<_>>()
+                .join(".");
+            (h, level, number)
+        })
+        .collect()
+}
+
+fn write_toc<W: Write>(
+    org: &Org,
+    sections: &[(Headline, usize, String)],
+    anchors: &HashMap<NodeId, String>,
+    options: &ExportOptions,
+    mut writer: W,
+) -> Result<(), Error> {
+    let depth = options.toc.unwrap_or(0);
+    let sections = sections
+        .iter()
+        .filter(|(_, level, _)| *level <= depth)
+        .collect::<Vec<_>>();
+    if sections.is_empty() {
+        return Ok(());
+    }
+    write!(writer, "<nav class=\"toc\">")?;
+    let mut open = 0;
+    for (h, level, number) in sections {
+        if *level > open {
+            for _ in open..*level {
+                write!(writer, "<ul>")?;
+            }
+            open = *level;
+        } else {
+            write!(writer, "</li>")?;
+            while open > *level {
+                write!(writer, "</ul></li>")?;
+                open -= 1;
+            }
+        }
+        let title = h.title(org);
+        write!(
+            writer,
+            "<li><a href=\"#{}\">",
+            HtmlEscape(&anchors[&h.headline_node()])
+        )?;
+        if options.num.is_some_and(|num| *level <= num) {
+            write!(writer, "<span class=\"section-number\">{}</span> ", number)?;
+        }
+        write!(writer, "{}</a>", HtmlEscape(&title.raw))?;
+    }
+    write!(writer, "</li>")?;
+    for _ in 1..open {
+        write!(writer, "</ul></li>")?;
+    }
+    write!(writer, "</ul></nav>")
+}
+
 fn write_headline_html<W, H, E>(
     org: &Org,
     headline: &Headline,
+    options: &ExportOptions,
     mut writer: W,
     handler: &mut H,
 ) -> Result<(), E>
@@ -302,16 +2016,35 @@ where
     H: HtmlHandler<E>,
 {
     let node_id = headline.headline_node();
+    let sections = sections(org, headline);
+    let numbers = sections
+        .iter()
+        .filter(|(_, level, _)| options.num.is_some_and(|num| *level <= num))
+        .map(|(h, _, number)| (h.headline_node(), number.as_str()))
+        .collect::<HashMap<_, _>>();
+    let anchors = heading_anchors(headline, org)
+        .into_iter()
+        .map(|(h, anchor)| (h.headline_node(), anchor))
+        .collect::<HashMap<_, _>>();
     for edge in node_id.traverse(org.arena()) {
         match edge {
             NodeEdge::Start(node) => {
                 let elem = &org[node];
                 match elem {
                     Element::Title(title) => {
-                        let mut title = title.clone();
                         // adjust all headline level started from 2 (<h2>)
-                        title.level = 2 + title.level - headline.level();
-                        handler.start(&mut writer, &Element::Title(title))?
+                        let level = (2 + title.level - headline.level()).min(6);
+                        let parent = org.arena()[node].parent();
+                        let anchor = parent.and_then(|parent| anchors.get(&parent));
+                        write!(
+                            writer,
+                            "<h{} id=\"{}\">",
+                            level,
+                            HtmlEscape(anchor.map_or("", |anchor| anchor.as_str()))
+                        )?;
+                        if let Some(number) = parent.and_then(|parent| numbers.get(&parent)) {
+                            write!(writer, "<span class=\"section-number\">{}</span> ", number)?;
+                        }
                     }
                     Element::SourceBlock(block) => {
                         let mut block = block.clone();
@@ -325,23 +2058,48 @@ where
                             .contents
                             .lines()
                             .map(|line| {
-                                line.strip_prefix(&indent).unwrap_or(line).to_string() + "\n"
+                                unescape_block_line(line.strip_prefix(&indent).unwrap_or(line))
+                                    .to_string()
+                                    + "\n"
                             })
                             .collect();
                         handler.start(&mut writer, &Element::SourceBlock(block))?
                     }
+                    Element::ExampleBlock(block) => {
+                        let mut block = block.clone();
+                        block.contents = block
+                            .contents
+                            .lines()
+                            .map(|line| unescape_block_line(line).to_string() + "\n")
+                            .collect();
+                        handler.start(&mut writer, &Element::ExampleBlock(block))?
+                    }
+                    Element::ExportBlock(block) => {
+                        let mut block = block.clone();
+                        block.contents = block
+                            .contents
+                            .lines()
+                            .map(|line| unescape_block_line(line).to_string() + "\n")
+                            .collect();
+                        handler.start(&mut writer, &Element::ExportBlock(block))?
+                    }
+                    // `#+begin_comment` blocks are never exported
+                    Element::CommentBlock(_) => {}
                     _ => handler.start(&mut writer, elem)?,
                 }
             }
             NodeEdge::End(node) => {
                 let elem = &org[node];
-                if let Element::Title(title) = elem {
-                    let mut title = title.clone();
-                    // adjust all headline level started from 2 (<h2>)
-                    title.level = 2 + title.level - headline.level();
-                    handler.end(&mut writer, &Element::Title(title))?
-                } else {
-                    handler.end(&mut writer, elem)?
+                match elem {
+                    Element::Title(title) => {
+                        let level = (2 + title.level - headline.level()).min(6);
+                        write!(writer, "</h{}>", level)?;
+                        if options.toc.is_some() && org.arena()[node].parent() == Some(node_id) {
+                            write_toc(org, &sections, &anchors, options, &mut writer)?;
+                        }
+                    }
+                    Element::CommentBlock(_) => {}
+                    _ => handler.end(&mut writer, elem)?,
                 }
             }
         }
@@ -1,21 +1,12 @@
 use super::*;
 use crate::{generator, site};
+use chrono::NaiveDate;
 use similar_asserts::assert_eq;
 use std::cell::RefCell;
-use std::sync::Once;
-
-static INIT: Once = Once::new();
-fn init() {
-    INIT.call_once(|| {
-        //std::env::set_var("TZ", "Asia/Tokyo");
-        std::env::set_var("TZ", "JST");
-    });
-}
+use std::rc::Rc;
 
 #[test]
 fn test_empty() {
-    init();
-
     let org_data = include_str!("empty.org");
     let expected = include_str!("empty.out.txt");
 
@@ -26,9 +17,10 @@ fn test_empty() {
         true,
         false,
     );
-    site.load_org_data(org_data.to_string());
+    site.time_zone = chrono_tz::Asia::Tokyo;
+    site.load_org_data(None, org_data.to_string());
 
-    generator::generate(Rc::new(site), generator::Output::Test(output.clone()))
+    generator::generate(Arc::new(site), generator::Output::Test(output.clone()))
         .expect("generator success");
 
     assert_eq!(output.borrow().as_str(), expected);
@@ -36,8 +28,6 @@ fn test_empty() {
 
 #[test]
 fn test_it() {
-    init();
-
     let org_data = include_str!("it.org");
     let expected = include_str!("it.out.txt");
 
@@ -48,9 +38,10 @@ fn test_it() {
         true,
         false,
     );
-    site.load_org_data(org_data.to_string());
+    site.time_zone = chrono_tz::Asia::Tokyo;
+    site.load_org_data(None, org_data.to_string());
 
-    generator::generate(Rc::new(site), generator::Output::Test(output.clone()))
+    generator::generate(Arc::new(site), generator::Output::Test(output.clone()))
         .expect("generator success");
 
     assert_eq!(output.borrow().as_str(), expected);
@@ -58,8 +49,6 @@ fn test_it() {
 
 #[test]
 fn test_draft() {
-    init();
-
     let org_data = include_str!("draft.org");
     let expected = include_str!("draft.out.txt");
 
@@ -70,10 +59,1836 @@ fn test_draft() {
         true,
         true,
     );
-    site.load_org_data(org_data.to_string());
+    site.time_zone = chrono_tz::Asia::Tokyo;
+    site.load_org_data(None, org_data.to_string());
 
-    generator::generate(Rc::new(site), generator::Output::Test(output.clone()))
+    generator::generate(Arc::new(site), generator::Output::Test(output.clone()))
         .expect("generator success");
 
     assert_eq!(output.borrow().as_str(), expected);
 }
+
+#[test]
+fn test_localize() {
+    let mut site = site::Site::new("Test Site".to_string(), None, false, false);
+    site.time_zone = chrono_tz::America::New_York;
+
+    let date = NaiveDate::from_ymd_opt(2025, 3, 9).unwrap();
+    // skipped by DST
+    let gap = site.localize(&date.and_hms_opt(2, 30, 0).unwrap());
+    assert_eq!(gap.to_rfc3339(), "2025-03-09T03:30:00-04:00");
+
+    let date = NaiveDate::from_ymd_opt(2025, 11, 2).unwrap();
+    // repeated by DST
+    let ambiguous = site.localize(&date.and_hms_opt(1, 30, 0).unwrap());
+    assert_eq!(ambiguous.to_rfc3339(), "2025-11-02T01:30:00-04:00");
+
+    // east of UTC
+    site.time_zone = chrono_tz::Europe::Berlin;
+    let date = NaiveDate::from_ymd_opt(2025, 3, 30).unwrap();
+    let gap = site.localize(&date.and_hms_opt(2, 30, 0).unwrap());
+    assert_eq!(gap.to_rfc3339(), "2025-03-30T03:30:00+02:00");
+}
+
+#[test]
+fn test_date_sources() {
+    let org_data = r#"* closed                                                               :blog:
+CLOSED: [2025-01-05 Sun 10:00]
+:PROPERTIES:
+:ID:       closed
+:END:
+* published property                                                   :blog:
+:PROPERTIES:
+:ID:       published
+:PUBLISHED: <2025-01-06 Mon>
+:END:
+* scheduled wins                                                       :blog:
+SCHEDULED: <2025-01-07 Tue 09:00> CLOSED: [2025-01-08 Wed 10:00]
+:PROPERTIES:
+:ID:       scheduled
+:END:
+"#;
+
+    let mut site = site::Site::new("Test Site".to_string(), None, false, false);
+    site.date_sources = vec![
+        site::DateSource::Scheduled,
+        site::DateSource::Closed,
+        site::DateSource::Property("PUBLISHED".to_string()),
+    ];
+    site.load_org_data(None, org_data.to_string());
+
+    let published = |id: &str| {
+        site.articles[&site::Id::new(id.to_string())]
+            .published
+            .to_string()
+    };
+    assert_eq!(published("closed"), "2025-01-05 10:00:00");
+    assert_eq!(published("published"), "2025-01-06 00:00:00");
+    assert_eq!(published("scheduled"), "2025-01-07 09:00:00");
+}
+
+#[test]
+fn test_future() {
+    let org_data = r#"* past                                                                 :blog:
+SCHEDULED: <2025-01-01 Wed 12:00>
+:PROPERTIES:
+:ID:       past
+:END:
+* future                                                               :blog:
+SCHEDULED: <2025-01-10 Fri 12:00>
+:PROPERTIES:
+:ID:       future
+:END:
+"#;
+    let now = NaiveDate::from_ymd_opt(2025, 1, 5)
+        .unwrap()
+        .and_hms_opt(0, 0, 0)
+        .unwrap()
+        .and_utc();
+
+    for include_future in [false, true] {
+        let output = Rc::new(RefCell::new(String::new()));
+        let mut site = site::Site::new(
+            "Test Site".to_string(),
+            Some(Url::parse("http://test.site/").unwrap()),
+            true,
+            false,
+        );
+        site.now = now;
+        site.include_future = include_future;
+        site.load_org_data(None, org_data.to_string());
+
+        assert_eq!(site.index.values().flatten().count(), 1);
+        assert_eq!(
+            site.last_update.map(|u| u.to_string()),
+            Some("2025-01-01 12:00:00".to_string())
+        );
+
+        generator::generate(Arc::new(site), generator::Output::Test(output.clone()))
+            .expect("generator success");
+
+        let output = output.borrow();
+        assert_eq!(output.contains("articles/e/future.html ("), include_future);
+        assert!(!output.contains(">future</a>"));
+        assert!(!output.contains("<title>future</title>"));
+    }
+}
+
+#[test]
+fn test_updated() {
+    let org_data = r#"* notes only                                                           :blog:
+SCHEDULED: <2025-01-01 Wed 12:00>
+:PROPERTIES:
+:ID:       notes
+:END:
+:LOGBOOK:
+- State "DONE"       from "TODO"       [2025-01-04 Sat 10:00]
+- Note taken on [2025-01-03 Fri 10:00] \\
+  fixed /typo/
+- Note taken on [2025-01-02 Thu 10:00] \\
+  added a section
+:END:
+body
+* updated property                                                     :blog:
+SCHEDULED: <2025-01-01 Wed 12:00>
+:PROPERTIES:
+:ID:       updated
+:UPDATED:  [2025-02-01 Sat 09:00]
+:END:
+:LOGBOOK:
+- Note taken on [2025-01-03 Fri 10:00] \\
+  ignored
+:END:
+"#;
+
+    let output = Rc::new(RefCell::new(String::new()));
+    let mut site = site::Site::new("Test Site".to_string(), None, false, false);
+    site.updated_from = vec![site::LogbookEntryKind::Note];
+    site.changelog = true;
+    site.load_org_data(None, org_data.to_string());
+
+    let updated = |id: &str| {
+        site.articles[&site::Id::new(id.to_string())]
+            .updated
+            .map(|u| u.to_string())
+    };
+    assert_eq!(updated("notes"), Some("2025-01-03 10:00:00".to_string()));
+    assert_eq!(updated("updated"), Some("2025-02-01 09:00:00".to_string()));
+
+    generator::generate(Arc::new(site), generator::Output::Test(output.clone()))
+        .expect("generator success");
+
+    assert!(output.borrow().contains(
+        r#"<li><time datetime="2025-01-03T10:00:00+00:00">2025-01-03</time> fixed typo</li>
+
+    <li><time datetime="2025-01-02T10:00:00+00:00">2025-01-02</time> added a section</li>"#
+    ));
+}
+
+#[test]
+fn test_diagnostics() {
+    let org_data = r#"* no date                                                              :blog:
+:PROPERTIES:
+:ID:       no-date
+:END:
+* no id                                                                :blog:
+SCHEDULED: <2025-01-01 Wed 12:00>
+* broken link                                                          :blog:
+SCHEDULED: <2025-01-01 Wed 12:00>
+:PROPERTIES:
+:ID:       broken-link
+:END:
+[[id:not-exist][link]]
+text [[*missing heading]]
+"#;
+
+    let output = Rc::new(RefCell::new(String::new()));
+    let mut site = site::Site::new("Test Site".to_string(), None, false, false);
+    site.load_org_data(Some("test.org".into()), org_data.to_string());
+    let site = Arc::new(site);
+    generator::generate(site.clone(), generator::Output::Test(output.clone()))
+        .expect("generator success");
+
+    let diagnostics = site
+        .diagnostics
+        .all()
+        .into_iter()
+        .map(|d| (d.kind, d.location.unwrap().to_string()))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        diagnostics,
+        vec![
+            (
+                diagnostics::Kind::MissingPublishDate,
+                "test.org:1".to_string()
+            ),
+            (diagnostics::Kind::MissingId, "test.org:5".to_string()),
+            // reported at the line of each link, like `imo check`
+            (diagnostics::Kind::BrokenLink, "test.org:12".to_string()),
+            (diagnostics::Kind::BrokenLink, "test.org:13".to_string()),
+        ]
+    );
+
+    let json: serde_json::Value = serde_json::from_str(&site.diagnostics.to_json()).unwrap();
+    assert_eq!(
+        json[1],
+        serde_json::json!({
+            "severity": "warning",
+            "kind": "missing-id",
+            "file": "test.org",
+            "line": 5,
+            "message": "headline \"no id\" has blog tag, but does not have ID",
+        })
+    );
+    assert_eq!(json.as_array().unwrap().len(), 4);
+}
+
+#[test]
+fn test_duplicate_ids() {
+    let a = r#"* article                                                              :blog:
+SCHEDULED: <2025-01-01 Wed 12:00>
+:PROPERTIES:
+:ID:       article
+:END:
+** nested article                                                      :blog:
+SCHEDULED: <2025-01-02 Thu 12:00>
+:PROPERTIES:
+:ID:       nested
+:END:
+"#;
+    let b = r#"* draft                                                               :draft:
+SCHEDULED: <2025-01-03 Fri 12:00>
+:PROPERTIES:
+:ID:       article
+:END:
+* another                                                              :blog:
+SCHEDULED: <2025-01-04 Sat 12:00>
+:PROPERTIES:
+:ID:       another
+:END:
+** sub-headline
+:PROPERTIES:
+:ID:       nested
+:END:
+"#;
+    let c = r#"* another again                                                        :blog:
+SCHEDULED: <2025-01-05 Sun 12:00>
+:PROPERTIES:
+:ID:       another
+:END:
+** sub-headline of skipped article
+:PROPERTIES:
+:ID:       orphan
+:END:
+* orphan                                                               :blog:
+SCHEDULED: <2025-01-06 Mon 12:00>
+:PROPERTIES:
+:ID:       orphan
+:END:
+"#;
+
+    let mut site = site::Site::new("Test Site".to_string(), None, false, false);
+    site.load_org_data(Some("a.org".into()), a.to_string());
+    site.load_org_data(Some("b.org".into()), b.to_string());
+    site.load_org_data(Some("c.org".into()), c.to_string());
+
+    let diagnostics = site
+        .diagnostics
+        .all()
+        .into_iter()
+        .map(|d| (d.severity, d.location.unwrap().to_string(), d.message))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        diagnostics,
+        vec![
+            (
+                diagnostics::Severity::Error,
+                "b.org:1".to_string(),
+                "ID \"article\" is already used at a.org:1".to_string()
+            ),
+            (
+                diagnostics::Severity::Error,
+                "b.org:11".to_string(),
+                "sub-headline ID \"nested\" is already used at a.org:6".to_string()
+            ),
+            (
+                diagnostics::Severity::Error,
+                "c.org:1".to_string(),
+                "ID \"another\" is already used at b.org:6".to_string()
+            ),
+        ]
+    );
+    // IDs of the skipped article's sub-headlines are free
+    assert!(site
+        .articles
+        .contains_key(&site::Id::new("orphan".to_string())));
+    // the first one wins
+    assert_eq!(
+        site.subid_to_articleid_map[&site::Id::new("nested".to_string())],
+        site::Id::new("article".to_string())
+    );
+}
+
+#[test]
+fn test_check() {
+    let org_data = r#"* published                                                            :blog:
+SCHEDULED: <2025-01-01 Wed 12:00>
+:PROPERTIES:
+:ID:       published
+:END:
+- [[id:not-exist][broken]]
+- [[id:draft][draft]]
+- [[file:not-exist.txt][missing file]]
+- [[https://example.com/image.png]]
+- [[id:sub][sub-headline of draft]]
+* draft                                                               :draft:
+SCHEDULED: <2025-01-02 Thu 12:00>
+:PROPERTIES:
+:ID:       draft
+:END:
+** sub
+:PROPERTIES:
+:ID:       sub
+:END:
+* future                                                               :blog:
+SCHEDULED: <2025-01-10 Fri 12:00>
+:PROPERTIES:
+:ID:       future
+:END:
+[[id:published][ok]]
+"#;
+
+    let mut site = site::Site::new("Test Site".to_string(), None, false, true);
+    site.include_future = true;
+    site.now = NaiveDate::from_ymd_opt(2025, 1, 5)
+        .unwrap()
+        .and_hms_opt(0, 0, 0)
+        .unwrap()
+        .and_utc();
+    site.load_org_data(Some("test.org".into()), org_data.to_string());
+    check::check(&site);
+
+    let diagnostics = site
+        .diagnostics
+        .all()
+        .into_iter()
+        .map(|d| (d.kind, d.location.unwrap().to_string()))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        diagnostics,
+        vec![
+            (diagnostics::Kind::BrokenLink, "test.org:6".to_string()),
+            (diagnostics::Kind::LinkToDraft, "test.org:7".to_string()),
+            (diagnostics::Kind::MissingFile, "test.org:8".to_string()),
+            (diagnostics::Kind::MissingAltText, "test.org:9".to_string()),
+            (diagnostics::Kind::LinkToDraft, "test.org:10".to_string()),
+            (diagnostics::Kind::FutureArticle, "test.org:20".to_string()),
+        ]
+    );
+}
+
+#[test]
+fn test_check_pages() {
+    let org_data = r#"* About                                                                 :page:
+- [[id:not-exist][broken]]
+- [[https://example.com/image.png]]
+* published                                                            :blog:
+SCHEDULED: <2025-01-01 Wed 12:00>
+:PROPERTIES:
+:ID:       published
+:END:
+[[id:hidden][hidden]]
+* hidden
+:PROPERTIES:
+:ID:       hidden
+:END:
+[[id:also-not-exist][broken]]
+"#;
+
+    let mut site = site::Site::new("Test Site".to_string(), None, false, true);
+    site.unresolved_links = site::UnresolvedLinks::Note;
+    site.load_org_data(Some("test.org".into()), org_data.to_string());
+    site.load_notes();
+    check::check(&site);
+
+    let diagnostics = site
+        .diagnostics
+        .all()
+        .into_iter()
+        .map(|d| (d.kind, d.message))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        diagnostics,
+        vec![
+            (
+                diagnostics::Kind::BrokenLink,
+                "id:not-exist not found".to_string()
+            ),
+            (
+                diagnostics::Kind::MissingAltText,
+                "image https://example.com/image.png has no description (alt text)".to_string()
+            ),
+            (
+                diagnostics::Kind::BrokenLink,
+                "id:also-not-exist not found".to_string()
+            ),
+        ]
+    );
+}
+
+#[test]
+fn test_cli() {
+    let cli = Cli::try_parse_from(["imo", "-n", "Test Site", "test.org"]).unwrap();
+    assert!(cli.command.is_none());
+    assert_eq!(cli.args.site_name.as_deref(), Some("Test Site"));
+    assert_eq!(cli.args.load.files, vec!["test.org".to_string()]);
+
+    let cli = Cli::try_parse_from(["imo", "check", "test.org"]).unwrap();
+    if let Some(Command::Check(args)) = cli.command {
+        assert_eq!(args.load.files, vec!["test.org".to_string()]);
+    } else {
+        panic!("check subcommand is not parsed");
+    }
+
+    // site name is required only to generate the site
+    assert!(Cli::try_parse_from(["imo", "test.org"]).is_err());
+    // options of site generation can't be given to check
+    assert!(Cli::try_parse_from(["imo", "check", "-n", "Test Site", "test.org"]).is_err());
+}
+
+#[test]
+fn test_link_to_draft() {
+    let org_data = r#"* published                                                            :blog:
+SCHEDULED: <2025-01-01 Wed 12:00>
+:PROPERTIES:
+:ID:       published
+:END:
+[[id:draft][to draft]] [[id:sub][to sub-headline of draft]]
+* draft                                                               :draft:
+SCHEDULED: <2025-01-02 Thu 12:00>
+:PROPERTIES:
+:ID:       draft
+:END:
+** sub
+:PROPERTIES:
+:ID:       sub
+:END:
+"#;
+
+    for include_draft in [false, true] {
+        let output = Rc::new(RefCell::new(String::new()));
+        let mut site = site::Site::new("Test Site".to_string(), None, false, include_draft);
+        site.load_org_data(Some("test.org".into()), org_data.to_string());
+        let site = Arc::new(site);
+        generator::generate(site.clone(), generator::Output::Test(output.clone()))
+            .expect("generator success");
+
+        let output = output.borrow();
+        if include_draft {
+            assert!(output.contains(
+                r#"<a class="draft" href="../../articles/t/draft.html">to draft</a> <a class="draft" href="../../articles/t/draft.html#sub">to sub-headline of draft</a>"#
+            ));
+        } else {
+            assert!(output.contains("<p>to draft to sub-headline of draft</p>"));
+        }
+        let diagnostics = site
+            .diagnostics
+            .all()
+            .into_iter()
+            .map(|d| (d.kind, d.message))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            diagnostics,
+            vec![
+                (
+                    diagnostics::Kind::LinkToDraft,
+                    "id:draft is a draft".to_string()
+                ),
+                (
+                    diagnostics::Kind::LinkToDraft,
+                    "id:sub is a draft".to_string()
+                ),
+            ]
+        );
+    }
+}
+
+#[test]
+fn test_unresolved_links() {
+    let org_data = r#"* published                                                            :blog:
+SCHEDULED: <2025-01-01 Wed 12:00>
+:PROPERTIES:
+:ID:       published
+:END:
+[[id:memo][memo]] [[id:private][private]]
+* memo
+:PROPERTIES:
+:ID:       memo
+:END:
+see [[id:another][another memo]]
+* another memo
+:PROPERTIES:
+:ID:       another
+:END:
+* private                                                           :PRIVATE:
+:PROPERTIES:
+:ID:       private
+:END:
+"#;
+
+    let cases = [
+        (
+            site::UnresolvedLinks::Text,
+            "<p>memo private</p>",
+            vec!["id:memo is not published", "id:private not found"],
+        ),
+        (
+            site::UnresolvedLinks::Span,
+            r#"<p><span class="unpublished" title="not published">memo</span> private</p>"#,
+            vec!["id:private not found"],
+        ),
+        (
+            site::UnresolvedLinks::Url("https://wiki.example.com/{id}".to_string()),
+            r#"<p><a href="https://wiki.example.com/memo">memo</a> <a href="https://wiki.example.com/private">private</a></p>"#,
+            vec!["id:private not found"],
+        ),
+        (
+            site::UnresolvedLinks::Note,
+            r#"<p><a class="note" href="../../notes/o/memo.html">memo</a> private</p>"#,
+            vec!["id:private not found"],
+        ),
+    ];
+    for (mode, expected, messages) in cases {
+        let output = Rc::new(RefCell::new(String::new()));
+        let mut site = site::Site::new("Test Site".to_string(), None, false, false);
+        site.unresolved_links = mode.clone();
+        site.load_org_data(None, org_data.to_string());
+        site.load_notes();
+        let site = Arc::new(site);
+        generator::generate(site.clone(), generator::Output::Test(output.clone()))
+            .expect("generator success");
+
+        let output = output.borrow();
+        assert!(output.contains(expected), "{:?}", mode);
+        assert_eq!(
+            site.diagnostics
+                .all()
+                .into_iter()
+                .map(|d| d.message)
+                .collect::<Vec<_>>(),
+            messages
+        );
+        if mode == site::UnresolvedLinks::Note {
+            assert_eq!(site.notes.len(), 2);
+            assert!(output.contains(
+                r#"see <a class="note" href="../../notes/r/another.html">another memo</a>"#
+            ));
+        }
+    }
+}
+
+#[test]
+fn test_note_options() {
+    let org_data = r#"#+LANGUAGE: ja
+#+OPTIONS: toc:1
+* published                                                            :blog:
+SCHEDULED: <2025-01-01 Wed 12:00>
+:PROPERTIES:
+:ID:       published
+:END:
+[[id:memo][memo]] [[id:][empty]]
+* memo
+:PROPERTIES:
+:ID:       memo
+:END:
+** part
+* empty ID
+:PROPERTIES:
+:ID:
+:END:
+"#;
+
+    let output = Rc::new(RefCell::new(String::new()));
+    let mut site = site::Site::new("Test Site".to_string(), None, false, false);
+    site.unresolved_links = site::UnresolvedLinks::Note;
+    site.load_org_data(None, org_data.to_string());
+    site.load_notes();
+    let site = Arc::new(site);
+    generator::generate(site.clone(), generator::Output::Test(output.clone()))
+        .expect("generator success");
+
+    let output = output.borrow();
+    let (_, note) = output.split_once("\nnotes/o/memo.html:\n").unwrap();
+    assert!(note.starts_with("<!doctype html>\n<html lang=\"ja\">"));
+    assert!(note.contains(r##"<nav class="toc"><ul><li><a href="#part">part</a>"##));
+    // headlines with an empty ID are never exported
+    assert_eq!(site.notes.len(), 1);
+
+    // the generator writes notes under "notes/"
+    assert!("notes".parse::<site::Section>().is_err());
+    assert!("notes/sub".parse::<site::Section>().is_err());
+    assert!("notebook".parse::<site::Section>().is_ok());
+}
+
+#[test]
+fn test_note_future() {
+    let org_data = r#"* published                                                            :blog:
+SCHEDULED: <2025-01-01 Wed 12:00>
+:PROPERTIES:
+:ID:       published
+:END:
+[[id:future][future]] [[id:future-part][part]]
+* future                                                               :blog:
+SCHEDULED: <2025-01-10 Fri 12:00>
+:PROPERTIES:
+:ID:       future
+:END:
+** part
+:PROPERTIES:
+:ID:       future-part
+:END:
+"#;
+
+    let output = Rc::new(RefCell::new(String::new()));
+    let mut site = site::Site::new("Test Site".to_string(), None, false, false);
+    site.unresolved_links = site::UnresolvedLinks::Note;
+    site.now = NaiveDate::from_ymd_opt(2025, 1, 5)
+        .unwrap()
+        .and_hms_opt(0, 0, 0)
+        .unwrap()
+        .and_utc();
+    site.load_org_data(None, org_data.to_string());
+    site.load_notes();
+    assert!(site.notes.is_empty());
+    let site = Arc::new(site);
+    generator::generate(site.clone(), generator::Output::Test(output.clone()))
+        .expect("generator success");
+
+    let output = output.borrow();
+    assert!(!output.contains("notes/"));
+    assert!(output.contains("<p>future part</p>"));
+    assert_eq!(
+        site.diagnostics
+            .all()
+            .into_iter()
+            .map(|d| (d.kind, d.message))
+            .collect::<Vec<_>>(),
+        vec![
+            (
+                diagnostics::Kind::FutureArticle,
+                "id:future is scheduled in the future".to_string()
+            ),
+            (
+                diagnostics::Kind::FutureArticle,
+                "id:future-part is scheduled in the future".to_string()
+            ),
+        ]
+    );
+}
+
+#[test]
+fn test_heading_links() {
+    let org_data = r#"* first                                                                :blog:
+SCHEDULED: <2025-01-01 Wed 12:00>
+:PROPERTIES:
+:ID:       first
+:END:
+[[*Some  Section]] [[#usage][usage]] [[*Other Section][other]] [[*missing]]
+** Some Section
+** Usage
+:PROPERTIES:
+:CUSTOM_ID: usage
+:END:
+* second                                                               :blog:
+SCHEDULED: <2025-01-02 Thu 12:00>
+:PROPERTIES:
+:ID:       second
+:END:
+[[*Some Section][same article first]]
+** Some Section
+** Other Section
+"#;
+
+    let output = Rc::new(RefCell::new(String::new()));
+    let mut site = site::Site::new("Test Site".to_string(), None, false, false);
+    site.load_org_data(None, org_data.to_string());
+    let site = Arc::new(site);
+    generator::generate(site.clone(), generator::Output::Test(output.clone()))
+        .expect("generator success");
+
+    let output = output.borrow();
+    assert!(output.contains(concat!(
+        r#"<p><a href="../../articles/t/first.html#some-section">Some  Section</a> "#,
+        r#"<a href="../../articles/t/first.html#usage">usage</a> "#,
+        r#"<a href="../../articles/d/second.html#other-section">other</a> missing</p>"#
+    )));
+    assert!(output.contains(r#"<h3 id="usage">"#));
+    assert!(output
+        .contains(r#"<a href="../../articles/d/second.html#some-section">same article first</a>"#));
+    assert_eq!(
+        site.diagnostics
+            .all()
+            .into_iter()
+            .map(|d| d.message)
+            .collect::<Vec<_>>(),
+        vec!["*missing not found"]
+    );
+}
+
+#[test]
+fn test_duplicate_headings() {
+    let org_data = r#"* first                                                                :blog:
+SCHEDULED: <2025-01-01 Wed 12:00>
+:PROPERTIES:
+:ID:       first
+:EXPORT_OPTIONS: toc:1
+:END:
+[[*Intro][intro]]
+** Intro
+** Intro
+** intro 1
+"#;
+
+    let output = Rc::new(RefCell::new(String::new()));
+    let mut site = site::Site::new("Test Site".to_string(), None, false, false);
+    site.load_org_data(None, org_data.to_string());
+    let site = Arc::new(site);
+    generator::generate(site.clone(), generator::Output::Test(output.clone()))
+        .expect("generator success");
+
+    let anchors = site.articles[&site::Id::new("first".to_string())]
+        .headings
+        .iter()
+        .map(|h| h.anchor.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(anchors, vec!["first", "intro", "intro-1", "intro-1-1"]);
+
+    let output = output.borrow();
+    assert!(output.contains(concat!(
+        r##"<li><a href="#intro">Intro</a></li>"##,
+        r##"<li><a href="#intro-1">Intro</a></li>"##,
+        r##"<li><a href="#intro-1-1">intro 1</a></li>"##,
+    )));
+    assert!(output.contains(r#"<h3 id="intro">"#));
+    assert!(output.contains(r#"<h3 id="intro-1">"#));
+    assert!(output.contains(r#"<h3 id="intro-1-1">"#));
+    assert!(output.contains(r#"<a href="../../articles/t/first.html#intro">intro</a>"#));
+}
+
+#[test]
+fn test_file_links() {
+    let first = r#"* first                                                                :blog:
+SCHEDULED: <2025-01-01 Wed 12:00>
+:PROPERTIES:
+:ID:       first
+:END:
+[[file:other.org]] [[file:./sub/../other.org::*Section][section]] [[file:more.org][more]]
+[[file:more.org::*memo][memo]] [[file:other.org::#missing]] [[file:image.png]]
+"#;
+    let other = r#"* other                                                                :blog:
+SCHEDULED: <2025-01-02 Thu 12:00>
+:PROPERTIES:
+:ID:       other
+:END:
+** Section
+"#;
+    let more = r#"* memo
+* more 1                                                               :blog:
+SCHEDULED: <2025-01-03 Fri 12:00>
+:PROPERTIES:
+:ID:       more1
+:END:
+* more 2                                                               :blog:
+SCHEDULED: <2025-01-04 Sat 12:00>
+:PROPERTIES:
+:ID:       more2
+:END:
+"#;
+
+    let output = Rc::new(RefCell::new(String::new()));
+    let mut site = site::Site::new("Test Site".to_string(), None, false, false);
+    site.load_org_data(Some("notes/first.org".into()), first.to_string());
+    site.load_org_data(Some("notes/other.org".into()), other.to_string());
+    site.load_org_data(Some("notes/more.org".into()), more.to_string());
+    let site = Arc::new(site);
+    generator::generate(site.clone(), generator::Output::Test(output.clone()))
+        .expect("generator success");
+
+    let output = output.borrow();
+    assert!(output.contains(concat!(
+        r#"<p><a href="../../articles/r/other.html">file:other.org</a> "#,
+        r#"<a href="../../articles/r/other.html#section">section</a> more"#,
+        "\nmemo file:other.org::#missing ",
+        r#"<a href="../../image.png"><img src="../../image.png"></a></p>"#
+    )));
+    assert_eq!(
+        site.diagnostics
+            .all()
+            .into_iter()
+            .map(|d| d.message)
+            .collect::<Vec<_>>(),
+        vec![
+            r#"file:more.org has 2 articles, specify one with "::*Heading""#,
+            "file:more.org::*memo is not published",
+            "file:other.org::#missing not found",
+        ]
+    );
+}
+
+#[test]
+fn test_parallel_determinism() {
+    // enough articles to be rendered in several chunks
+    let files = (0..8)
+        .map(|f| {
+            let data = (0..20)
+                .map(|i| {
+                    let id = f * 20 + i;
+                    format!(
+                        "* article {id}                                                          :blog:
+SCHEDULED: <2025-01-{:02} Wed 12:00>
+:PROPERTIES:
+:ID:       a{id}
+:END:
+[[id:a{}][next]] [[id:missing{id}][missing]] [[*nowhere{id}]]
+",
+                        id % 28 + 1,
+                        id + 1
+                    )
+                })
+                .collect::<String>();
+            (Some(PathBuf::from(format!("{}.org", f))), data)
+        })
+        .collect::<Vec<_>>();
+
+    let generate = |threads: usize| {
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build()
+            .unwrap();
+        pool.install(|| {
+            let mut site = site::Site::new(
+                "Test Site".to_string(),
+                Some(Url::parse("http://test.site/").unwrap()),
+                true,
+                false,
+            );
+            let org_files = files
+                .par_iter()
+                .map(|(file, data)| site::OrgFile::parse(file.clone(), data.clone()))
+                .collect::<Vec<_>>();
+            for org_file in org_files {
+                site.load_org_file(org_file);
+            }
+            let site = Arc::new(site);
+            let output = Rc::new(RefCell::new(String::new()));
+            generator::generate(site.clone(), generator::Output::Test(output.clone()))
+                .expect("generator success");
+            let output = output.borrow().clone();
+            (output, site.diagnostics.all())
+        })
+    };
+
+    let (output, diagnostics) = generate(1);
+    assert_eq!(diagnostics.len(), 160 * 2 + 1);
+    for _ in 0..2 {
+        let (parallel_output, parallel_diagnostics) = generate(4);
+        assert_eq!(parallel_output, output);
+        assert_eq!(parallel_diagnostics, diagnostics);
+    }
+}
+
+#[test]
+fn test_inputs() {
+    let root = std::env::temp_dir().join(format!("imo-test-inputs-{}", std::process::id()));
+    for (path, data) in [
+        ("a/x.org", "* x"),
+        ("a/b/y.org", "* y"),
+        ("a/note.txt", "not org"),
+        (".hidden/z.org", "* z"),
+        ("drafts/d.org", "* d"),
+        ("ignored.org", "* i"),
+        (".gitignore", "ignored.org"),
+    ] {
+        let path = root.join(path);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, data).unwrap();
+    }
+    let input = |path: &str| root.join(path).to_str().unwrap().to_string();
+
+    let files = inputs::collect(
+        &[input(""), input("a/*.org"), input("ignored.org")],
+        &["drafts/".to_string()],
+    )
+    .unwrap();
+    std::fs::remove_dir_all(&root).unwrap();
+
+    assert_eq!(
+        files,
+        vec![
+            root.join("a/b/y.org"),
+            root.join("a/x.org"),
+            root.join("ignored.org")
+        ]
+    );
+}
+
+#[test]
+fn test_inputs_glob_exclude() {
+    let root = std::env::temp_dir().join(format!("imo-test-glob-{}", std::process::id()));
+    for path in [
+        "a/x.org",
+        "drafts/d.org",
+        "drafts/sub/e.org",
+        "b/drafts/f.org",
+    ] {
+        let path = root.join(path);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, "* x").unwrap();
+    }
+    let input = |path: &str| root.join(path).to_str().unwrap().to_string();
+
+    let files = inputs::collect(
+        &[input("*/*.org"), input("*/*/*.org")],
+        &["drafts/".to_string()],
+    )
+    .unwrap();
+    std::fs::remove_dir_all(&root).unwrap();
+
+    assert_eq!(files, vec![root.join("a/x.org")]);
+}
+
+#[test]
+fn test_include() {
+    let root = std::env::temp_dir().join(format!("imo-test-include-{}", std::process::id()));
+    std::fs::create_dir_all(&root).unwrap();
+    for (path, data) in [
+        (
+            "main.org",
+            r#"#+SETUPFILE: setup.org
+* article                                                              :blog:
+SCHEDULED: <2025-01-01 Wed 12:00>
+:PROPERTIES:
+:ID:       article
+:END:
+#+INCLUDE: "part.org"
+#+INCLUDE: "code.rs" src rust :lines "2-3"
+#+INCLUDE: "snip.html" export html
+#+INCLUDE: "main.org"
+#+INCLUDE: "missing.org"
+"#,
+        ),
+        ("setup.org", "#+TITLE: setup\nnot a keyword\n"),
+        (
+            "part.org",
+            "* Part heading\ntext from part\n** no date                                                           :blog:\n",
+        ),
+        ("code.rs", "fn a() {}\n* not a headline\nfn b() {}\n"),
+        ("snip.html", "<pre>\n#+foo\n* star\n</pre>\n"),
+    ] {
+        std::fs::write(root.join(path), data).unwrap();
+    }
+
+    let main = root.join("main.org");
+    let data = std::fs::read_to_string(&main).unwrap();
+    let expanded = include::expand(Some(&main), &data);
+    assert!(expanded.text.starts_with("#+TITLE: setup\n* article"));
+
+    let output = Rc::new(RefCell::new(String::new()));
+    let mut site = site::Site::new("Test Site".to_string(), None, false, false);
+    site.load_org_data(Some(main), data);
+    let site = Arc::new(site);
+    generator::generate(site.clone(), generator::Output::Test(output.clone()))
+        .expect("generator success");
+    std::fs::remove_dir_all(&root).unwrap();
+
+    let output = output.borrow();
+    assert!(
+        output.contains(r#"<h3 id="part-heading">Part heading</h3><section><p>text from part</p>"#)
+    );
+    assert!(output.contains(r#"<h4 id="no-date">no date</h4>"#));
+    // escaped by "," in the block, but not rendered
+    assert!(output.contains("not a headline") && !output.contains(",*"));
+    assert!(!output.contains("fn b()"));
+    assert!(output.contains("<pre>\n#+foo\n* star\n</pre>\n"));
+    assert_eq!(
+        site.diagnostics
+            .all()
+            .into_iter()
+            .map(|d| {
+                let location = d.location.unwrap();
+                let file = location.file.unwrap();
+                let file = file.file_name().unwrap().to_str().unwrap().to_string();
+                (d.kind, format!("{}:{}", file, location.line))
+            })
+            .collect::<Vec<_>>(),
+        vec![
+            (diagnostics::Kind::IncludeCycle, "main.org:10".to_string()),
+            (diagnostics::Kind::MissingFile, "main.org:11".to_string()),
+            (
+                diagnostics::Kind::MissingPublishDate,
+                "part.org:3".to_string()
+            ),
+        ]
+    );
+}
+
+#[test]
+fn test_file_keywords() {
+    let org_data = r#"#+AUTHOR: Alice
+#+LANGUAGE: ja
+#+FILETAGS: :memo:rust:
+#+OPTIONS: toc:t num:1 ^:{}
+* first                                                            :blog:rust:
+SCHEDULED: <2025-01-01 Wed 12:00>
+:PROPERTIES:
+:ID:       first
+:END:
+H_{2}O and x^2
+** one
+** two
+*** two-one
+* second                                                               :blog:
+SCHEDULED: <2025-01-02 Thu 12:00>
+:PROPERTIES:
+:ID:       second
+:EXPORT_AUTHOR: Bob
+:EXPORT_OPTIONS: toc:nil num:nil ^:t
+:END:
+x^2
+** three
+"#;
+
+    let output = Rc::new(RefCell::new(String::new()));
+    let mut site = site::Site::new(
+        "Test Site".to_string(),
+        Some(Url::parse("http://test.site/").unwrap()),
+        true,
+        false,
+    );
+    site.load_org_data(None, org_data.to_string());
+    let site = Arc::new(site);
+    generator::generate(site.clone(), generator::Output::Test(output.clone()))
+        .expect("generator success");
+
+    let first = &site.articles[&site::Id::new("first".to_string())];
+    assert_eq!(first.tags, vec!["memo", "rust"]);
+    assert_eq!(first.author.as_deref(), Some("Alice"));
+    assert_eq!(first.language.as_deref(), Some("ja"));
+
+    let output = output.borrow();
+    assert!(output.contains(r#"<html lang="ja">"#));
+    assert!(output.contains(r#"<meta name="author" content="Bob">"#));
+    assert!(output.contains(concat!(
+        r##"<nav class="toc"><ul><li><a href="#one"><span class="section-number">1</span> one</a></li>"##,
+        r##"<li><a href="#two"><span class="section-number">2</span> two</a><ul><li><a href="#two-one">two-one</a></li></ul></li></ul></nav>"##
+    )));
+    assert!(output.contains(r#"<h3 id="one"><span class="section-number">1</span> one</h3>"#));
+    assert!(output.contains(r#"<h4 id="two-one">two-one</h4>"#));
+    assert!(output.contains("H<sub>2</sub>O and x^2"));
+    assert!(output.contains("<p>x<sup>2</sup></p>"));
+    assert!(output.contains(r#"<h3 id="three">three</h3>"#));
+    assert!(output.contains("<author><name>Bob</name></author>"));
+}
+
+#[test]
+fn test_tag_inheritance() {
+    let org_data = r#"#+FILETAGS: :memo:
+* posts                                                           :blog:rust:
+** first
+SCHEDULED: <2025-01-01 Wed 12:00>
+:PROPERTIES:
+:ID:       first
+:END:
+*** secret                                                          :noexport:
+**** not published either
+** second                                                             :draft:
+SCHEDULED: <2025-01-02 Thu 12:00>
+:PROPERTIES:
+:ID:       second
+:END:
+* private                                                            :PRIVATE:
+** hidden                                                               :blog:
+SCHEDULED: <2025-01-03 Fri 12:00>
+:PROPERTIES:
+:ID:       hidden
+:END:
+* own                                                                   :blog:
+SCHEDULED: <2025-01-04 Sat 12:00>
+:PROPERTIES:
+:ID:       own
+:END:
+"#;
+
+    let load = |mode, exclude: Vec<String>| {
+        let mut site = site::Site::new("Test Site".to_string(), None, false, true);
+        site.blog_tag_mode = mode;
+        site.tags_exclude_from_inheritance = exclude;
+        site.load_org_data(None, org_data.to_string());
+        site
+    };
+    let ids = |articles: &std::collections::BTreeMap<site::Id, Arc<site::Article>>| {
+        articles.keys().map(|id| id.to_string()).collect::<Vec<_>>()
+    };
+
+    let site = load(site::BlogTagMode::Own, vec![]);
+    assert_eq!(ids(&site.articles), vec!["own"]);
+    assert_eq!(ids(&site.drafts), vec!["second"]);
+
+    let site = load(site::BlogTagMode::Children, vec![]);
+    assert_eq!(ids(&site.articles), vec!["first"]);
+    assert_eq!(ids(&site.drafts), vec!["second"]);
+    let first = &site.articles[&site::Id::new("first".to_string())];
+    assert_eq!(first.tags, vec!["memo", "rust"]);
+    assert_eq!(first.headings.len(), 1);
+
+    let site = load(site::BlogTagMode::Children, vec!["memo".to_string()]);
+    let first = &site.articles[&site::Id::new("first".to_string())];
+    assert_eq!(first.tags, vec!["rust"]);
+}
+
+#[test]
+fn test_excluded_headlines() {
+    let org_data = r#"* first                                                                 :blog:
+SCHEDULED: <2025-01-01 Wed 12:00>
+:PROPERTIES:
+:ID:       first
+:END:
+visible
+#+begin_comment
+this is a comment
+#+end_comment
+** COMMENT commented
+not published
+*** nor its children
+** internal                                                          :internal:
+internal things
+** noexport tag is not excluded by --exclude-tags                   :noexport:
+* COMMENT commented article                                             :blog:
+SCHEDULED: <2025-01-02 Thu 12:00>
+:PROPERTIES:
+:ID:       commented
+:END:
+"#;
+
+    let render = |exclude_tags: Vec<String>| {
+        let output = Rc::new(RefCell::new(String::new()));
+        let mut site = site::Site::new("Test Site".to_string(), None, false, false);
+        if !exclude_tags.is_empty() {
+            site.exclude_tags = exclude_tags;
+        }
+        site.load_org_data(None, org_data.to_string());
+        let site = Arc::new(site);
+        generator::generate(site.clone(), generator::Output::Test(output.clone()))
+            .expect("generator success");
+        assert_eq!(site.articles.len(), 1);
+        let output = output.borrow().clone();
+        output
+    };
+
+    let output = render(vec![]);
+    assert!(output.contains("visible"));
+    assert!(!output.contains("this is a comment"));
+    assert!(!output.contains("commented"));
+    assert!(!output.contains("nor its children"));
+    assert!(output.contains("internal things"));
+    assert!(!output.contains("noexport tag"));
+
+    let output = render(vec!["internal".to_string()]);
+    assert!(!output.contains("internal things"));
+    assert!(output.contains("noexport tag"));
+}
+
+#[test]
+fn test_tag_match() {
+    let org_data = r#"* team                                                                 :team:
+** first                                                                :blog:
+SCHEDULED: <2025-01-01 Wed 12:00>
+:PROPERTIES:
+:ID:       first
+:END:
+** second                                                      :blog:internal:
+SCHEDULED: <2025-01-02 Thu 12:00>
+:PROPERTIES:
+:ID:       second
+:END:
+** third                                                            :wip:blog:
+SCHEDULED: <2025-01-03 Fri 12:00>
+:PROPERTIES:
+:ID:       third
+:END:
+* personal                                                              :blog:
+SCHEDULED: <2025-01-04 Sat 12:00>
+:PROPERTIES:
+:ID:       personal
+:END:
+* note                                                                 :notes:
+SCHEDULED: <2025-01-05 Sun 12:00>
+:PROPERTIES:
+:ID:       note
+:END:
+* series                                                                :blog:
+SCHEDULED: <2025-01-06 Mon 12:00>
+:PROPERTIES:
+:ID:       series
+:END:
+** part                                                                 :team:
+SCHEDULED: <2025-01-07 Tue 12:00>
+:PROPERTIES:
+:ID:       part
+:END:
+"#;
+
+    let load = |publish: &str, draft: &str| {
+        let mut site = site::Site::new("Test Site".to_string(), None, false, true);
+        site.publish_tags = publish.parse().unwrap();
+        site.draft_tags = draft.parse().unwrap();
+        site.load_org_data(None, org_data.to_string());
+        let ids = |articles: &std::collections::BTreeMap<site::Id, Arc<site::Article>>| {
+            articles.keys().map(|id| id.to_string()).collect::<Vec<_>>()
+        };
+        (ids(&site.articles), ids(&site.drafts))
+    };
+
+    let (articles, drafts) = load("blog", "draft");
+    assert_eq!(
+        articles,
+        vec!["first", "personal", "second", "series", "third"]
+    );
+    assert!(drafts.is_empty());
+
+    // "first" inherits "team", "part" inherits "blog"
+    let (articles, drafts) = load("blog+team-internal-wip", "blog+team+wip");
+    assert_eq!(articles, vec!["first", "part"]);
+    assert_eq!(drafts, vec!["third"]);
+    // the order of terms doesn't matter
+    assert_eq!(
+        load("team+blog-wip-internal", "wip+team+blog"),
+        (articles, drafts)
+    );
+
+    // sub-headlines of "team" only inherit it
+    let (articles, _) = load("team", "draft");
+    assert_eq!(articles, vec!["part"]);
+
+    let (articles, _) = load("blog-team|notes", "draft");
+    assert_eq!(articles, vec!["note", "personal", "series"]);
+
+    assert!("".parse::<site::TagMatch>().is_err());
+    assert!("-internal".parse::<site::TagMatch>().is_err());
+    assert!("blog+".parse::<site::TagMatch>().is_err());
+}
+
+#[test]
+fn test_sections() {
+    let org_data = r#"* post                                                                  :blog:
+SCHEDULED: <2025-01-01 Wed 12:00>
+:PROPERTIES:
+:ID:       post
+:END:
+see [[id:note][the note]]
+* note                                                             :blog:memos:
+SCHEDULED: <2025-01-02 Thu 12:00>
+:PROPERTIES:
+:ID:       note
+:END:
+* old note                                                         :blog:memos:
+SCHEDULED: <2024-01-02 Tue 12:00>
+:PROPERTIES:
+:ID:       old-note
+:END:
+* talk                                                                  :blog:
+SCHEDULED: <2025-01-03 Fri 12:00>
+:PROPERTIES:
+:ID:       talk
+:SECTION:  talks
+:END:
+* unknown section                                                       :blog:
+SCHEDULED: <2025-01-04 Sat 12:00>
+:PROPERTIES:
+:ID:       unknown
+:SECTION:  nowhere
+:END:
+"#;
+
+    let output = Rc::new(RefCell::new(String::new()));
+    let mut site = site::Site::new(
+        "Test Site".to_string(),
+        Some(url::Url::parse("http://test.site/").unwrap()),
+        true,
+        false,
+    );
+    site.sections = vec!["memos".parse().unwrap(), "talks=talk".parse().unwrap()];
+    site.load_org_data(None, org_data.to_string());
+    let site = Arc::new(site);
+    generator::generate(site.clone(), generator::Output::Test(output.clone()))
+        .expect("generator success");
+
+    let ids = |index: &std::collections::BTreeMap<site::Year, _>| {
+        index
+            .values()
+            .flat_map(|articles: &std::collections::BTreeSet<Arc<site::Article>>| articles.iter())
+            .map(|article| article.id.to_string())
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(ids(&site.index), vec!["post", "unknown"]);
+    assert_eq!(ids(&site.sections[0].index), vec!["old-note", "note"]);
+    assert_eq!(ids(&site.sections[1].index), vec!["talk"]);
+    assert_eq!(site.diagnostics.count(diagnostics::Severity::Warning), 1);
+
+    let output = output.borrow();
+    assert!(output.contains("\nmemos/index.html (2025-01-02 12:00:00 UTC):\n"));
+    assert!(output.contains("<title>memos - Test Site</title>"));
+    assert!(output.contains(
+        r#"<link href="../memos/atom.xml" type="application/atom+xml" rel="alternate">"#
+    ));
+    assert!(output.contains(r#"<a href="../articles/e/note.html">note</a>"#));
+    assert!(output.contains(r#"<h3><a href="../memos/2024.html">2024 (1)</a></h3>"#));
+    assert!(output.contains("\nmemos/2024.html (2024-01-02 12:00:00 UTC):\n"));
+    assert!(output.contains("\ntalks/index.html (2025-01-03 12:00:00 UTC):\n"));
+    assert!(output.contains("</feed>memos/atom.xml (2025-01-02 12:00:00 UTC):\n"));
+    assert!(output.contains("<title>talks - Test Site</title><id>http://test.site/talks/</id>"));
+    // links across sections
+    assert!(output.contains(r#"see <a href="../../articles/e/note.html">the note</a>"#));
+}
+
+#[test]
+fn test_pages() {
+    let org_data = r#"* About                                                                 :page:
+:PROPERTIES:
+:ID:       about
+:END:
+about [[id:first][first article]]
+** Contact
+:PROPERTIES:
+:ID:       contact
+:END:
+* My Projects
+:PROPERTIES:
+:EXPORT_PAGE: projects/index
+:END:
+projects
+* first                                                                 :blog:
+SCHEDULED: <2025-01-01 Wed 12:00>
+:PROPERTIES:
+:ID:       first
+:END:
+see [[id:about][about]], [[id:contact][contact]] and [[id:projects][projects]]
+* broken
+:PROPERTIES:
+:EXPORT_PAGE: ../outside
+:END:
+"#;
+
+    let output = Rc::new(RefCell::new(String::new()));
+    let mut site = site::Site::new("Test Site".to_string(), None, false, false);
+    site.load_org_data(None, org_data.to_string());
+    let site = Arc::new(site);
+    generator::generate(site.clone(), generator::Output::Test(output.clone()))
+        .expect("generator success");
+
+    assert_eq!(
+        site.pages.keys().collect::<Vec<_>>(),
+        vec!["about.html", "projects/index.html"]
+    );
+    assert_eq!(site.articles.len(), 1);
+    assert_eq!(site.index.values().flatten().count(), 1);
+
+    let output = output.borrow();
+    assert!(output.contains("\nabout.html:\n"));
+    assert!(output.contains("<title>About - Test Site</title>"));
+    assert!(output.contains(r#"<p>about <a href="articles/t/first.html">first article</a></p>"#));
+    assert!(output.contains("\nprojects/index.html:\n"));
+    assert!(output.contains(r#"<link rel="stylesheet" href="../static/style.css">"#));
+    assert!(output.contains(concat!(
+        r#"see <a href="../../about.html">about</a>, "#,
+        r#"<a href="../../about.html#contact">contact</a> and projects"#
+    )));
+    let warnings = site
+        .diagnostics
+        .all()
+        .into_iter()
+        .map(|d| d.message)
+        .collect::<Vec<_>>();
+    assert_eq!(
+        warnings,
+        vec![
+            "id:projects not found",
+            "headline \"broken\" has invalid EXPORT_PAGE: ../outside",
+        ]
+    );
+}
+
+#[test]
+fn test_page_paths() {
+    let org_data = r#"* Index                                                                 :page:
+* Archive                                                               :page:
+* year
+:PROPERTIES:
+:EXPORT_PAGE: 2025
+:END:
+* month
+:PROPERTIES:
+:EXPORT_PAGE: 2025/01.html
+:END:
+* article
+:PROPERTIES:
+:EXPORT_PAGE: articles/t/first.html
+:END:
+* in section
+:PROPERTIES:
+:EXPORT_PAGE: tech/about
+:END:
+* !?                                                                    :page:
+* Index                                                                 :page:
+:PROPERTIES:
+:EXPORT_PAGE: site/index
+:END:
+"#;
+
+    let mut site = site::Site::new("Test Site".to_string(), None, false, false);
+    site.sections = vec!["tech".parse().unwrap()];
+    site.load_org_data(Some("test.org".into()), org_data.to_string());
+
+    assert_eq!(
+        site.pages.keys().collect::<Vec<_>>(),
+        vec!["site/index.html"]
+    );
+    let warnings = site
+        .diagnostics
+        .all()
+        .into_iter()
+        .map(|d| d.message)
+        .collect::<Vec<_>>();
+    assert_eq!(
+        warnings,
+        vec![
+            "page \"index.html\" would overwrite the index",
+            "page \"archive.html\" would overwrite the archives",
+            "page \"2025.html\" would overwrite an archive",
+            "page \"2025/01.html\" would overwrite an archive",
+            "page \"articles/t/first.html\" would overwrite articles",
+            "page \"tech/about.html\" would overwrite a section",
+            "page path can't be made from headline \"!?\", set EXPORT_PAGE",
+        ]
+    );
+}
+
+#[test]
+fn test_menu() {
+    let org_data = r#"* About                                                                 :page:
+:PROPERTIES:
+:ID:       about
+:END:
+* first                                                                 :blog:
+SCHEDULED: <2025-01-01 Wed 12:00>
+:PROPERTIES:
+:ID:       first
+:END:
+** section
+:PROPERTIES:
+:ID:       first-section
+:END:
+* memo
+:PROPERTIES:
+:ID:       memo
+:END:
+"#;
+
+    let render = |mode: site::UnresolvedLinks| {
+        let output = Rc::new(RefCell::new(String::new()));
+        let mut site = site::Site::new("Test Site".to_string(), None, false, false);
+        site.unresolved_links = mode;
+        site.menu = [
+            "About=id:about",
+            "Section=id:first-section",
+            "Archive=2024.html",
+            "Source=https://example.com/src",
+            "Missing=id:missing",
+            "Memo=id:memo",
+        ]
+        .iter()
+        .map(|item| item.parse().unwrap())
+        .collect();
+        site.load_org_data(None, org_data.to_string());
+        site.load_notes();
+        let site = Arc::new(site);
+        generator::generate(site.clone(), generator::Output::Test(output.clone()))
+            .expect("generator success");
+        let messages = site
+            .diagnostics
+            .all()
+            .into_iter()
+            .map(|d| d.message)
+            .collect::<Vec<_>>();
+        let output = output.borrow().clone();
+        (output, messages)
+    };
+
+    let menu = |base: &str, memo: &str| {
+        format!(
+            concat!(
+                r#"<nav class="menu"><ul><li><a href="{0}about.html">About</a></li>"#,
+                r#"<li><a href="{0}articles/t/first.html#first-section">Section</a></li>"#,
+                r#"<li><a href="{0}2024.html">Archive</a></li>"#,
+                r#"<li><a href="https://example.com/src">Source</a></li>"#,
+                r#"<li>Missing</li><li>{1}</li></ul></nav>"#
+            ),
+            base,
+            memo.replace("{base}", base)
+        )
+    };
+    let (output, messages) = render(site::UnresolvedLinks::Text);
+    assert!(output.contains(&format!("</h1>\n                {}\n", menu("", "Memo"))));
+    assert!(output.contains(&menu("../../", "Memo")));
+    assert_eq!(
+        messages,
+        vec![
+            "menu \"Memo\": id:memo is not published",
+            "menu \"Missing\": id:missing is not published"
+        ]
+    );
+
+    let (output, messages) = render(site::UnresolvedLinks::Span);
+    let span = r#"<span class="unpublished" title="not published">Memo</span>"#;
+    assert!(output.contains(&menu("../../", span)));
+    assert_eq!(
+        messages,
+        vec!["menu \"Missing\": id:missing is not published"]
+    );
+
+    // headlines linked only from the menu are exported as notes too
+    let (output, messages) = render(site::UnresolvedLinks::Note);
+    assert!(output.contains(&menu(
+        "../../",
+        r#"<a href="{base}notes/o/memo.html">Memo</a>"#
+    )));
+    assert!(output.contains("\nnotes/o/memo.html:\n"));
+    assert_eq!(
+        messages,
+        vec!["menu \"Missing\": id:missing is not published"]
+    );
+
+    assert!("no target".parse::<site::MenuItem>().is_err());
+}
+
+#[test]
+fn test_archives() {
+    let org_data = r#"* old                                                                   :blog:
+SCHEDULED: <2024-12-31 Tue 12:00>
+:PROPERTIES:
+:ID:       old
+:END:
+* first                                                                 :blog:
+SCHEDULED: <2025-01-01 Wed 12:00>
+:PROPERTIES:
+:ID:       first
+:END:
+* second                                                                :blog:
+SCHEDULED: <2025-01-15 Wed 12:00>
+:PROPERTIES:
+:ID:       second
+:END:
+* third                                                                 :blog:
+SCHEDULED: <2025-03-01 Sat 12:00>
+:PROPERTIES:
+:ID:       third
+:END:
+"#;
+
+    let output = Rc::new(RefCell::new(String::new()));
+    let mut site = site::Site::new("Test Site".to_string(), None, false, false);
+    site.load_org_data(None, org_data.to_string());
+    generator::generate(Arc::new(site), generator::Output::Test(output.clone()))
+        .expect("generator success");
+
+    let output = output.borrow();
+    let file = |header: &str| {
+        let (_, rest) = output.split_once(&format!("\n{}:\n", header)).unwrap();
+        rest.split_once("</html>").unwrap().0.to_string()
+    };
+    // every year including the newest one, and every month which has articles
+    let newest = file("2025.html (2025-03-01 12:00:00 UTC)");
+    assert!(newest.contains(r#"<a href="articles/d/third.html">third</a>"#));
+    assert!(newest.contains(r#"<a href="articles/t/first.html">first</a>"#));
+    assert!(!newest.contains("old.html"));
+    let january = file("2025/01.html (2025-01-15 12:00:00 UTC)");
+    assert!(january.contains(r#"<link rel="stylesheet" href="../static/style.css">"#));
+    assert!(january.contains(r#"<a href="../articles/d/second.html">second</a>"#));
+    assert!(january.contains(r#"<a href="../articles/t/first.html">first</a>"#));
+    assert!(!january.contains("third.html"));
+    assert!(output.contains("\n2025/03.html (2025-03-01 12:00:00 UTC):\n"));
+    assert!(output.contains("\n2024.html (2024-12-31 12:00:00 UTC):\n"));
+    assert!(output.contains("\n2024/12.html (2024-12-31 12:00:00 UTC):\n"));
+    assert!(!output.contains("\n2025/02.html"));
+
+    let archive = file("archive.html (2025-03-01 12:00:00 UTC)");
+    let entries = archive
+        .lines()
+        .map(str::trim)
+        .filter(|line| {
+            ["<h2>", "<h3>", "<li>"]
+                .iter()
+                .any(|tag| line.starts_with(tag))
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        entries,
+        vec![
+            r#"<h2><a href="2025.html">2025</a></h2>"#,
+            r#"<h3><a href="2025/03.html">2025-03</a></h3>"#,
+            r#"<li><time datetime="2025-03-01T12:00:00+00:00">2025-03-01</time> <a href="articles/d/third.html">third</a></li>"#,
+            r#"<h3><a href="2025/01.html">2025-01</a></h3>"#,
+            r#"<li><time datetime="2025-01-15T12:00:00+00:00">2025-01-15</time> <a href="articles/d/second.html">second</a></li>"#,
+            r#"<li><time datetime="2025-01-01T12:00:00+00:00">2025-01-01</time> <a href="articles/t/first.html">first</a></li>"#,
+            r#"<h2><a href="2024.html">2024</a></h2>"#,
+            r#"<h3><a href="2024/12.html">2024-12</a></h3>"#,
+            r#"<li><time datetime="2024-12-31T12:00:00+00:00">2024-12-31</time> <a href="articles/d/old.html">old</a></li>"#,
+        ]
+    );
+}
+
+#[test]
+fn test_calendar() {
+    let org_data = r#"* first                                                                 :blog:
+SCHEDULED: <2025-01-01 Wed 12:00>
+:PROPERTIES:
+:ID:       first
+:END:
+* second                                                                :blog:
+SCHEDULED: <2025-01-15 Wed 12:00>
+:PROPERTIES:
+:ID:       second
+:END:
+* third                                                                 :blog:
+SCHEDULED: <2025-03-01 Sat 12:00>
+:PROPERTIES:
+:ID:       third
+:END:
+"#;
+
+    let output = Rc::new(RefCell::new(String::new()));
+    let mut site = site::Site::new("Test Site".to_string(), None, false, false);
+    site.calendar = true;
+    site.load_org_data(None, org_data.to_string());
+    generator::generate(Arc::new(site), generator::Output::Test(output.clone()))
+        .expect("generator success");
+
+    let output = output.borrow();
+    assert!(output.contains(concat!(
+        "<ol class=\"calendar\">\n",
+        "    <li><a href=\"2025/01.html\">Jan</a> <span class=\"count\">2</span></li>\n",
+        "    <li class=\"empty\">Feb <span class=\"count\">0</span></li>\n",
+        "    <li><a href=\"2025/03.html\">Mar</a> <span class=\"count\">1</span></li>\n",
+        "    <li class=\"empty\">Apr <span class=\"count\">0</span></li>\n",
+    )));
+    assert!(output.contains("<li class=\"empty\">Dec <span class=\"count\">0</span></li>\n</ol>"));
+    // month archives don't have calendars
+    assert_eq!(output.matches("<ol class=\"calendar\">").count(), 1);
+}
+
+#[test]
+fn test_series() {
+    let org_data = r#"* Rust tutorial
+:PROPERTIES:
+:SERIES:   Rust tutorial
+:END:
+** part two                                                             :blog:
+SCHEDULED: <2025-01-02 Thu 12:00>
+:PROPERTIES:
+:ID:       two
+:END:
+** part one                                                             :blog:
+SCHEDULED: <2025-01-01 Wed 12:00>
+:PROPERTIES:
+:ID:       one
+:END:
+* part three                                                            :blog:
+SCHEDULED: <2025-01-03 Fri 12:00>
+:PROPERTIES:
+:ID:       three
+:SERIES:   Rust tutorial
+:END:
+* standalone                                                            :blog:
+SCHEDULED: <2025-01-04 Sat 12:00>
+:PROPERTIES:
+:ID:       standalone
+:END:
+* C                                                                     :blog:
+SCHEDULED: <2025-01-05 Sun 12:00>
+:PROPERTIES:
+:ID:       c
+:SERIES:   C
+:END:
+* C++                                                                   :blog:
+SCHEDULED: <2025-01-06 Mon 12:00>
+:PROPERTIES:
+:ID:       cpp
+:SERIES:   C++
+:END:
+* symbols                                                               :blog:
+SCHEDULED: <2025-01-07 Tue 12:00>
+:PROPERTIES:
+:ID:       symbols
+:SERIES:   ???
+:END:
+"#;
+
+    let output = Rc::new(RefCell::new(String::new()));
+    let mut site = site::Site::new("Test Site".to_string(), None, false, false);
+    site.load_org_data(None, org_data.to_string());
+    let site = Arc::new(site);
+    generator::generate(site.clone(), generator::Output::Test(output.clone()))
+        .expect("generator success");
+
+    assert_eq!(
+        site.series["Rust tutorial"]
+            .iter()
+            .map(|article| article.id.to_string())
+            .collect::<Vec<_>>(),
+        vec!["one", "two", "three"]
+    );
+    assert_eq!(
+        site.articles[&site::Id::new("standalone".to_string())].series,
+        None
+    );
+
+    let output = output.borrow();
+    assert!(output.contains(concat!(
+        "<nav class=\"series\">\n",
+        "<p>Part 2 of 3 in <a href=\"../../series/rust-tutorial.html\">Rust tutorial</a></p>\n",
+        "<ol>\n",
+        "    <li><a href=\"../../articles/e/one.html\">part one</a></li>\n",
+        "    <li class=\"current\">part two</li>\n",
+        "    <li><a href=\"../../articles/e/three.html\">part three</a></li>\n",
+        "</ol>\n",
+        "</nav><h2",
+    )));
+    assert_eq!(output.matches("<nav class=\"series\">").count(), 6);
+    assert!(output.contains("\nseries/rust-tutorial.html (2025-01-03 12:00:00 UTC):\n"));
+    // series names with the same (or no) slug
+    assert_eq!(site.series_path("C"), "series/c.html");
+    assert_eq!(site.series_path("C++"), "series/c-1.html");
+    assert_eq!(site.series_path("???"), "series/series.html");
+    assert!(output.contains("\nseries/c-1.html (2025-01-06 12:00:00 UTC):\n"));
+    assert!(output.contains("<title>Rust tutorial - Test Site</title>"));
+}
+
+#[test]
+fn test_related() {
+    let org_data = r#"* target                                                       :blog:rust:web:
+SCHEDULED: <2025-01-05 Sun 12:00>
+:PROPERTIES:
+:ID:       target
+:END:
+see [[id:linked-sub][linked]]
+* same tags                                                    :blog:rust:web:
+SCHEDULED: <2025-01-01 Wed 12:00>
+:PROPERTIES:
+:ID:       same-tags
+:END:
+* linked                                                                :blog:
+SCHEDULED: <2025-01-02 Thu 12:00>
+:PROPERTIES:
+:ID:       linked
+:END:
+** sub
+:PROPERTIES:
+:ID:       linked-sub
+:END:
+* one tag (older)                                                  :blog:rust:
+SCHEDULED: <2025-01-03 Fri 12:00>
+:PROPERTIES:
+:ID:       older
+:END:
+* one tag (newer)                                                   :blog:web:
+SCHEDULED: <2025-01-04 Sat 12:00>
+:PROPERTIES:
+:ID:       newer
+:END:
+* links to target                                                       :blog:
+SCHEDULED: <2024-12-01 Sun 12:00>
+:PROPERTIES:
+:ID:       backlink
+:END:
+[[id:target]]
+* unrelated                                                        :blog:misc:
+SCHEDULED: <2025-01-06 Mon 12:00>
+:PROPERTIES:
+:ID:       unrelated
+:END:
+"#;
+
+    let load = |related| {
+        let mut site = site::Site::new("Test Site".to_string(), None, false, false);
+        site.related = related;
+        site.load_org_data(None, org_data.to_string());
+        site
+    };
+    let related = |site: &site::Site, id: &str| {
+        let links = site.article_links();
+        let article = &site.articles[&site::Id::new(id.to_string())];
+        site.related_articles(article, &links)
+            .iter()
+            .map(|article| article.id.to_string())
+            .collect::<Vec<_>>()
+    };
+
+    let site = load(10);
+    assert_eq!(
+        related(&site, "target"),
+        vec!["linked", "same-tags", "backlink", "newer", "older"]
+    );
+    assert!(related(&site, "unrelated").is_empty());
+    assert_eq!(related(&load(2), "target"), vec!["linked", "same-tags"]);
+    assert!(related(&load(0), "target").is_empty());
+
+    let output = Rc::new(RefCell::new(String::new()));
+    generator::generate(Arc::new(load(1)), generator::Output::Test(output.clone()))
+        .expect("generator success");
+    assert!(output.borrow().contains(concat!(
+        "<section class=\"related\">\n<h3>Related articles</h3>\n<ul>\n",
+        "    <li><time datetime=\"2025-01-02T12:00:00+00:00\">2025-01-02</time> ",
+        "<a href=\"../../articles/d/linked.html\">linked</a></li>\n",
+        "</ul>\n</section>\n"
+    )));
+}